@@ -1,144 +1,143 @@
-/// Basic Settlement Example
-/// 
-/// This example demonstrates how to create and submit a basic settlement
+//! Basic Settlement Example
+//!
+//! This example demonstrates how to build the instructions for a basic settlement
 
 use settlement_program::{
-    CompleteTrade, SettlementData, SettlementInstruction, SettlementSummary, Side,
+    instruction, BatchId, CompleteTrade, FeeTier, MarketLimits, MarketParams, PriceE6, QtyE6, Side,
+    UsdcE6,
 };
-use solana_sdk::{
-    instruction::{AccountMeta, Instruction},
-    pubkey::Pubkey,
-    signature::Keypair,
-    signer::Signer,
-    transaction::Transaction,
-};
-use std::str::FromStr;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
 
 fn main() {
     println!("=== Basic Settlement Example ===\n");
 
+    let program_id = settlement_program::id();
+    let admin = Keypair::new();
+    let relayer = Keypair::new();
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
     // 1. Create a sample trade
     let trade = CompleteTrade {
         id: "trade_example_001".to_string(),
         market: "BTC-PERP".to_string(),
-        
+
         // Price: 105,315 USDC (e6 format)
-        price_e6: 105315000000,
-        
+        price_e6: PriceE6(105315000000),
+
         // Quantity: 0.001 BTC (e6 format)
-        qty_e6: 1000,
-        
+        qty_e6: QtyE6(1000),
+
         // Notional: price * qty / 1_000_000
-        notional_e6: 105315000,
-        
+        notional_e6: UsdcE6::notional(PriceE6(105315000000), QtyE6(1000)).unwrap(),
+
         taker_side: Side::Sell,
-        ts_ms: chrono::Utc::now().timestamp_millis(),
+        ts_ms: 1762897603000,
         engine_seq: 1,
-        
+
         taker_order_id: "ord_taker_001".to_string(),
         maker_order_id: "ord_maker_001".to_string(),
-        
+
         taker_account_id: "account_taker".to_string(),
         maker_account_id: "account_maker".to_string(),
-        taker_wallet: Pubkey::default(),
-        maker_wallet: Pubkey::default(),
-        
+        taker_wallet: taker,
+        maker_wallet: maker,
+
         taker_leverage: 20,
         maker_leverage: 20,
-        
+
         // Taker fee: 0.045% = 47,391
-        taker_fee_e6: 47391,
-        
+        taker_fee_e6: UsdcE6(47391),
+
         // Maker fee: 0.015% = 15,797
-        maker_fee_e6: 15797,
-        
-        fee_rate_taker_bp: 45,  // 0.045%
-        fee_rate_maker_bp: 15,  // 0.015%
+        maker_fee_e6: UsdcE6(15797),
+
+        fee_rate_taker_e5: 45,  // 0.045%
+        fee_rate_maker_e5: 15,  // 0.015%
     };
 
     println!("Trade created:");
     println!("  ID: {}", trade.id);
     println!("  Market: {}", trade.market);
-    println!("  Price: {} USDC", trade.price_e6 as f64 / 1_000_000.0);
-    println!("  Quantity: {} BTC", trade.qty_e6 as f64 / 1_000_000.0);
-    println!("  Notional: {} USDC", trade.notional_e6 as f64 / 1_000_000.0);
+    println!("  Price: {} USDC", trade.price_e6);
+    println!("  Quantity: {} BTC", trade.qty_e6);
+    println!("  Notional: {} USDC", trade.notional_e6);
     println!();
 
-    // 2. Create settlement data
-    let batch_id = uuid::Uuid::new_v4().to_string();
-    let total_volume_e6 = trade.notional_e6;
-    let total_fees_e6 = trade.taker_fee_e6 + trade.maker_fee_e6;
-
-    let mut settlement_data = SettlementData {
-        batch_id: batch_id.clone(),
-        timestamp_ms: chrono::Utc::now().timestamp_millis(),
-        relayer: Pubkey::default(),
-        trades: vec![trade],
-        accounts: vec![],
-        block_height: 0,
-        tx_signature: [0u8; 64],
-        total_volume_e6,
-        total_fees_e6,
-        data_hash: [0u8; 32],
-    };
+    // 2. The market must be registered by the admin (once per market)
+    let register = instruction::register_market(
+        &program_id,
+        &admin.pubkey(),
+        &trade.market,
+        MarketParams::STANDARD,
+        MarketLimits {
+            tick_size: PriceE6(100_000), // 0.1 USDC
+            lot_size: QtyE6(1_000),      // 0.001 BTC
+            max_leverage: 50,
+        },
+    )
+    .expect("build RegisterMarket");
+
+    println!("RegisterMarket instruction created:");
+    println!("  Market PDA: {}", register.accounts[2].pubkey);
+    println!();
 
-    // Calculate data hash
-    settlement_data.data_hash = calculate_hash(&settlement_data);
+    // 3. The admin sets the fee tiers the trade fees are checked against
+    //    (rates in 1/100_000: 45 = 0.045%)
+    let fee_schedule = instruction::set_fee_schedule(
+        &program_id,
+        &admin.pubkey(),
+        vec![FeeTier {
+            min_volume_e6: UsdcE6::ZERO,
+            taker_rate_e5: 45,
+            maker_rate_e5: 15,
+        }],
+        1, // 0.000001 USDC rounding tolerance
+    )
+    .expect("build SetFeeSchedule");
 
-    println!("Settlement data created:");
-    println!("  Batch ID: {}", settlement_data.batch_id);
-    println!("  Total volume: {} USDC", total_volume_e6 as f64 / 1_000_000.0);
-    println!("  Total fees: {} USDC", total_fees_e6 as f64 / 1_000_000.0);
-    println!("  Trades: {}", settlement_data.trades.len());
+    println!("SetFeeSchedule instruction created:");
+    println!("  FeeSchedule PDA: {}", fee_schedule.accounts[2].pubkey);
     println!();
 
-    // 3. Create instruction
-    let instruction_data = SettlementInstruction::RecordSettlement {
-        data: settlement_data,
-    };
+    // 4. Make sure both users have a UserSettlement account
+    let init_taker = instruction::initialize_user(&program_id, &relayer.pubkey(), &taker)
+        .expect("build InitializeUser");
+    let init_maker = instruction::initialize_user(&program_id, &relayer.pubkey(), &maker)
+        .expect("build InitializeUser");
 
-    println!("Instruction created: RecordSettlement");
+    println!("InitializeUser instructions created:");
+    println!("  Taker PDA: {}", init_taker.accounts[0].pubkey);
+    println!("  Maker PDA: {}", init_maker.accounts[0].pubkey);
     println!();
 
-    // 4. Derive PDA
-    let program_id = Pubkey::from_str("Sett1ementProg11111111111111111111111111111")
-        .unwrap();
-    
-    let (settlement_pda, bump) = Pubkey::find_program_address(
-        &[b"settlement", batch_id.as_bytes()],
+    // 5. Create the RecordSettlement instruction
+    let batch_id = "79307220-9abf-4f14-a22d-e8b5eebbc40b".to_string();
+    let record = instruction::record_settlement(
         &program_id,
-    );
+        &relayer.pubkey(),
+        BatchId::Uuid(batch_id.clone()),
+        vec![trade],
+        // market_id assigned by RegisterMarket (first registered market)
+        &[("BTC-PERP", 0)],
+    )
+    .expect("build RecordSettlement");
 
-    println!("PDA derived:");
-    println!("  Address: {}", settlement_pda);
+    println!("Instruction created: RecordSettlement");
+    println!("  Batch ID: {}", batch_id);
+    println!("  Accounts: {}", record.accounts.len());
+    println!("  Data: {} bytes", record.data.len());
+    println!();
+
+    let (config_pda, bump) = instruction::find_config_address(&program_id);
+    println!("Config PDA:");
+    println!("  Address: {}", config_pda);
     println!("  Bump: {}", bump);
     println!();
 
     println!("✅ Settlement prepared successfully!");
     println!("\nNext steps:");
-    println!("  1. Serialize instruction_data with borsh");
-    println!("  2. Create Solana transaction");
-    println!("  3. Sign with authority");
-    println!("  4. Send to 1024Chain");
+    println!("  1. Create Solana transaction");
+    println!("  2. Sign with an authorized relayer");
+    println!("  3. Send to 1024Chain");
 }
-
-// Helper function to calculate hash
-fn calculate_hash(data: &SettlementData) -> [u8; 32] {
-    use sha2::{Digest, Sha256};
-    
-    let mut hasher = Sha256::new();
-    hasher.update(data.batch_id.as_bytes());
-    hasher.update(&data.timestamp_ms.to_le_bytes());
-    
-    for trade in &data.trades {
-        hasher.update(trade.id.as_bytes());
-        hasher.update(&trade.price_e6.to_le_bytes());
-        hasher.update(&trade.qty_e6.to_le_bytes());
-    }
-    
-    let result = hasher.finalize();
-    let mut hash = [0u8; 32];
-    hash.copy_from_slice(&result);
-    hash
-}
-
@@ -1,4 +1,4 @@
-/// Settlement Account数据解析工具
+//! Settlement Account数据解析工具
 fn main() {
     println!("请使用命令行查询：");
     println!("solana account 5vFPPyqdFH3zgt7ArtqE1PFfmuRKaXycdUhmfMkPP9tr");
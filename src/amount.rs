@@ -0,0 +1,335 @@
+//! Fixed-point e6 Amounts
+//!
+//! 价格、数量和USDC金额统一使用e6定点数（1_000_000 = 1.0）。
+//! newtype与i64的Borsh编码和内存布局完全一致，可直接用于链上账户和指令数据。
+//!
+//! 所有乘除法都通过 `div_round` 在i128中计算并显式指定取整方式，
+//! 成交额的取整规则只在 `NOTIONAL_ROUNDING` 中定义。
+
+use std::fmt;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::{Pod, Zeroable};
+
+use crate::error::SettlementError;
+
+/// e6定点数的缩放因子
+pub const E6: i64 = 1_000_000;
+
+/// 取整方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// 向零截断
+    TowardZero,
+    /// 远离零（有余数时绝对值+1）
+    AwayFromZero,
+    /// 四舍五入（0.5远离零）
+    HalfAwayFromZero,
+}
+
+/// 成交额取整方式：向零截断（与撮合引擎一致）
+pub const NOTIONAL_ROUNDING: Rounding = Rounding::TowardZero;
+
+/// 计算 a * b / denominator，中间结果使用i128，按rounding取整
+pub fn mul_div(a: i64, b: i64, denominator: i64, rounding: Rounding) -> Result<i64, SettlementError> {
+    // i64 * i64 不会超出i128
+    div_round(a as i128 * b as i128, denominator as i128, rounding)
+}
+
+/// 计算 numerator / denominator，按rounding取整，结果需在i64范围内
+pub fn div_round(numerator: i128, denominator: i128, rounding: Rounding) -> Result<i64, SettlementError> {
+    if denominator == 0 {
+        return Err(SettlementError::ArithmeticOverflow);
+    }
+    
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    
+    let round_away = match rounding {
+        Rounding::TowardZero => false,
+        Rounding::AwayFromZero => remainder != 0,
+        Rounding::HalfAwayFromZero => remainder.unsigned_abs() * 2 >= denominator.unsigned_abs(),
+    };
+    
+    let quotient = if !round_away {
+        quotient
+    } else if (numerator < 0) != (denominator < 0) {
+        quotient - 1
+    } else {
+        quotient + 1
+    };
+    
+    i64::try_from(quotient).map_err(|_| SettlementError::ArithmeticOverflow)
+}
+
+/// 以十进制字符串输出定点数（去掉小数末尾的0），如 (47391, 6) -> "0.047391"
+fn fmt_decimal(raw: i128, decimals: u8, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let sign = if raw < 0 { "-" } else { "" };
+    let abs = raw.unsigned_abs();
+    let scale = 10u128.pow(decimals as u32);
+    let integer = abs / scale;
+    let fraction = abs % scale;
+    
+    if fraction == 0 {
+        write!(f, "{}{}", sign, integer)
+    } else {
+        let fraction = format!("{:0width$}", fraction, width = decimals as usize);
+        write!(f, "{}{}.{}", sign, integer, fraction.trim_end_matches('0'))
+    }
+}
+
+/// 按指定小数位数输出的定点数，见 `with_decimals`
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    raw: i128,
+    decimals: u8,
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_decimal(self.raw, self.decimals, f)
+    }
+}
+
+macro_rules! e6_newtype {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[repr(transparent)]
+        #[derive(
+            BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default,
+            PartialEq, Eq, PartialOrd, Ord, Hash, Pod, Zeroable,
+        )]
+        pub struct $name(pub i64);
+        
+        impl $name {
+            pub const ZERO: Self = Self(0);
+            
+            pub const fn new(raw: i64) -> Self {
+                Self(raw)
+            }
+            
+            pub const fn is_positive(self) -> bool {
+                self.0 > 0
+            }
+            
+            pub const fn is_negative(self) -> bool {
+                self.0 < 0
+            }
+            
+            pub fn checked_add(self, other: Self) -> Result<Self, SettlementError> {
+                self.0.checked_add(other.0)
+                    .map(Self)
+                    .ok_or(SettlementError::ArithmeticOverflow)
+            }
+            
+            pub fn checked_sub(self, other: Self) -> Result<Self, SettlementError> {
+                self.0.checked_sub(other.0)
+                    .map(Self)
+                    .ok_or(SettlementError::ArithmeticOverflow)
+            }
+            
+            pub fn checked_neg(self) -> Result<Self, SettlementError> {
+                self.0.checked_neg()
+                    .map(Self)
+                    .ok_or(SettlementError::ArithmeticOverflow)
+            }
+            
+            /// self * numerator / denominator（按比例缩放，如费率、分成）
+            pub fn checked_mul_div(
+                self,
+                numerator: i64,
+                denominator: i64,
+                rounding: Rounding,
+            ) -> Result<Self, SettlementError> {
+                mul_div(self.0, numerator, denominator, rounding).map(Self)
+            }
+            
+            /// 按decimals位小数输出（小数位数不超过 `MarketParams::MAX_DECIMALS`），
+            /// 用于报价或数量精度不是6位的市场，见 `MarketParams`
+            pub fn with_decimals(self, decimals: u8) -> Decimal {
+                Decimal { raw: self.0 as i128, decimals }
+            }
+        }
+        
+        /// 固定按6位小数输出；精度不是6位的市场用 `with_decimals`
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt_decimal(self.0 as i128, 6, f)
+            }
+        }
+    };
+}
+
+e6_newtype!(
+    /// 价格（USDC / 单位合约, e6）
+    PriceE6
+);
+
+e6_newtype!(
+    /// 数量（合约单位, e6）
+    QtyE6
+);
+
+e6_newtype!(
+    /// USDC金额（成交额、手续费等, e6）
+    UsdcE6
+);
+
+impl PriceE6 {
+    /// price * qty / 1e6
+    pub fn checked_mul(self, qty: QtyE6, rounding: Rounding) -> Result<UsdcE6, SettlementError> {
+        mul_div(self.0, qty.0, E6, rounding).map(UsdcE6)
+    }
+}
+
+impl UsdcE6 {
+    /// 标准合约（6位小数、乘数1）的成交额：price * qty / 1e6，按NOTIONAL_ROUNDING取整
+    ///
+    /// 其他精度或乘数的市场见 `Market::notional`。
+    pub fn notional(price: PriceE6, qty: QtyE6) -> Result<Self, SettlementError> {
+        price.checked_mul(qty, NOTIONAL_ROUNDING)
+    }
+    
+    /// amount * 1e6 / qty（如由成交额计算均价）
+    pub fn checked_div(self, qty: QtyE6, rounding: Rounding) -> Result<PriceE6, SettlementError> {
+        mul_div(self.0, E6, qty.0, rounding).map(PriceE6)
+    }
+}
+
+/// 128位USDC累计金额（e6），用于可能超出i64的生命周期累计值
+///
+/// 以两个u64（低位在前）存储，保持8字节对齐以便在account数据上zero-copy读写；
+/// 内存布局和Borsh编码与little-endian的i128完全一致。
+#[repr(transparent)]
+#[derive(
+    BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default,
+    PartialEq, Eq, Hash, Pod, Zeroable,
+)]
+pub struct WideUsdcE6([u64; 2]);
+
+impl WideUsdcE6 {
+    pub const ZERO: Self = Self([0; 2]);
+    
+    pub const fn new(raw: i128) -> Self {
+        Self([raw as u64, (raw >> 64) as u64])
+    }
+    
+    /// 以i128返回数值
+    pub const fn get(self) -> i128 {
+        ((self.0[1] as i64 as i128) << 64) | self.0[0] as i128
+    }
+    
+    pub fn checked_add(self, amount: UsdcE6) -> Result<Self, SettlementError> {
+        self.get().checked_add(amount.0 as i128)
+            .map(Self::new)
+            .ok_or(SettlementError::ArithmeticOverflow)
+    }
+    
+    pub fn checked_sub(self, amount: UsdcE6) -> Result<Self, SettlementError> {
+        self.get().checked_sub(amount.0 as i128)
+            .map(Self::new)
+            .ok_or(SettlementError::ArithmeticOverflow)
+    }
+}
+
+impl From<UsdcE6> for WideUsdcE6 {
+    fn from(amount: UsdcE6) -> Self {
+        Self::new(amount.0 as i128)
+    }
+}
+
+impl fmt::Display for WideUsdcE6 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_decimal(self.get(), 6, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_notional_matches_legacy_formula() {
+        let price = PriceE6(105315000000);
+        let qty = QtyE6(1000);
+        assert_eq!(UsdcE6::notional(price, qty), Ok(UsdcE6(105315000)));
+        
+        // 截断而非四舍五入
+        assert_eq!(UsdcE6::notional(PriceE6(1_999_999), QtyE6(1)), Ok(UsdcE6(1)));
+    }
+    
+    #[test]
+    fn test_rounding_modes() {
+        // 7 / 2 = 3.5
+        assert_eq!(mul_div(7, 1, 2, Rounding::TowardZero), Ok(3));
+        assert_eq!(mul_div(7, 1, 2, Rounding::AwayFromZero), Ok(4));
+        assert_eq!(mul_div(7, 1, 2, Rounding::HalfAwayFromZero), Ok(4));
+        assert_eq!(mul_div(-7, 1, 2, Rounding::TowardZero), Ok(-3));
+        assert_eq!(mul_div(-7, 1, 2, Rounding::AwayFromZero), Ok(-4));
+        assert_eq!(mul_div(-7, 1, 2, Rounding::HalfAwayFromZero), Ok(-4));
+        
+        // 7 / 3 = 2.33
+        assert_eq!(mul_div(7, 1, 3, Rounding::HalfAwayFromZero), Ok(2));
+        assert_eq!(mul_div(7, 1, 3, Rounding::AwayFromZero), Ok(3));
+        
+        // 整除时不调整
+        assert_eq!(mul_div(-6, 1, 3, Rounding::AwayFromZero), Ok(-2));
+    }
+    
+    #[test]
+    fn test_overflow_and_division_by_zero() {
+        assert_eq!(
+            PriceE6(i64::MAX).checked_mul(QtyE6(2 * E6), Rounding::TowardZero),
+            Err(SettlementError::ArithmeticOverflow)
+        );
+        assert_eq!(UsdcE6(i64::MAX).checked_add(UsdcE6(1)), Err(SettlementError::ArithmeticOverflow));
+        assert_eq!(UsdcE6(i64::MIN).checked_neg(), Err(SettlementError::ArithmeticOverflow));
+        assert_eq!(UsdcE6(1).checked_div(QtyE6::ZERO, Rounding::TowardZero),
+            Err(SettlementError::ArithmeticOverflow));
+    }
+    
+    #[test]
+    fn test_average_price() {
+        let notional = UsdcE6(105315000);
+        assert_eq!(notional.checked_div(QtyE6(1000), Rounding::TowardZero), Ok(PriceE6(105315000000)));
+    }
+    
+    #[test]
+    fn test_display_decimal() {
+        assert_eq!(PriceE6(105315000000).to_string(), "105315");
+        assert_eq!(QtyE6(1000).to_string(), "0.001");
+        assert_eq!(UsdcE6(47391).to_string(), "0.047391");
+        assert_eq!(UsdcE6(-15797).to_string(), "-0.015797");
+        assert_eq!(UsdcE6(-1_500_000).to_string(), "-1.5");
+        assert_eq!(UsdcE6(i64::MIN).to_string(), "-9223372036854.775808");
+        
+        // 8位小数报价、3位小数数量
+        assert_eq!(PriceE6(300_012_500_000).with_decimals(8).to_string(), "3000.125");
+        assert_eq!(QtyE6(2_500).with_decimals(3).to_string(), "2.5");
+        assert_eq!(QtyE6(-7).with_decimals(0).to_string(), "-7");
+        assert_eq!(UsdcE6(i64::MIN).with_decimals(18).to_string(), "-9.223372036854775808");
+    }
+    
+    #[test]
+    fn test_wide_amount() {
+        for raw in [0, 1, -1, i64::MAX as i128 + 1, i64::MIN as i128 - 1, i128::MAX, i128::MIN] {
+            let amount = WideUsdcE6::new(raw);
+            assert_eq!(amount.get(), raw);
+            assert_eq!(amount.try_to_vec().unwrap(), raw.try_to_vec().unwrap());
+            assert_eq!(bytemuck::bytes_of(&amount), &raw.to_le_bytes());
+        }
+        
+        let amount = WideUsdcE6::from(UsdcE6(i64::MAX)).checked_add(UsdcE6(i64::MAX)).unwrap();
+        assert_eq!(amount.get(), 2 * i64::MAX as i128);
+        assert_eq!(amount.to_string(), "18446744073709.551614");
+        assert_eq!(WideUsdcE6::from(UsdcE6(-15797)).to_string(), "-0.015797");
+        assert_eq!(WideUsdcE6::new(i128::MAX).checked_add(UsdcE6(1)), Err(SettlementError::ArithmeticOverflow));
+        assert_eq!(WideUsdcE6::new(i128::MIN).checked_sub(UsdcE6(1)), Err(SettlementError::ArithmeticOverflow));
+    }
+    
+    #[test]
+    fn test_borsh_matches_i64() {
+        let price = PriceE6(105315000000);
+        assert_eq!(price.try_to_vec().unwrap(), 105315000000i64.try_to_vec().unwrap());
+    }
+}
@@ -43,6 +43,66 @@ pub enum SettlementError {
     
     #[error("Invalid trade - price, qty, or notional mismatch")]
     InvalidTrade = 12,
+    
+    #[error("Invalid config account")]
+    InvalidConfigAccount = 13,
+    
+    #[error("Too many relayers")]
+    TooManyRelayers = 14,
+    
+    #[error("Relayer not found in config")]
+    RelayerNotFound = 15,
+    
+    #[error("Relayer already registered")]
+    RelayerAlreadyExists = 16,
+    
+    #[error("Unauthorized admin - signer is not the config admin")]
+    UnauthorizedAdmin = 17,
+    
+    #[error("Program is paused")]
+    ProgramPaused = 18,
+    
+    #[error("Market is paused")]
+    MarketPaused = 19,
+    
+    #[error("Invalid merkle proof - trade not included in batch")]
+    InvalidMerkleProof = 20,
+    
+    #[error("Sequence gap - engine_seq skipped a number")]
+    SequenceGap = 21,
+    
+    #[error("Sequence replay - engine_seq not greater than last settled")]
+    SequenceReplay = 22,
+    
+    #[error("Arithmetic overflow")]
+    ArithmeticOverflow = 23,
+    
+    #[error("Unknown market - not registered")]
+    UnknownMarket = 24,
+    
+    #[error("Too many markets")]
+    TooManyMarkets = 25,
+    
+    #[error("Invalid market parameters")]
+    InvalidMarketParams = 26,
+    
+    #[error("Market is delisted")]
+    MarketDelisted = 27,
+    
+    #[error("Invalid fee schedule")]
+    InvalidFeeSchedule = 28,
+    
+    #[error("Fee mismatch - rate or amount does not match the fee schedule")]
+    FeeMismatch = 29,
+    
+    #[error("Invalid referrer - self-referral, empty, or referrer already set")]
+    InvalidReferrer = 30,
+    
+    #[error("Invalid funding - empty payments or duplicate wallet")]
+    InvalidFunding = 31,
+    
+    #[error("Funding imbalance - payments do not net to zero within tolerance")]
+    FundingImbalance = 32,
 }
 
 impl From<SettlementError> for ProgramError {
@@ -1,34 +1,934 @@
 //! Settlement Program Instructions
 
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::pubkey::Pubkey;
-use crate::state::CompleteTrade;
+use solana_program::{
+    address_lookup_table::AddressLookupTableAccount,
+    bpf_loader_upgradeable,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    message::v0,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
+use crate::{
+    amount::UsdcE6,
+    error::SettlementError,
+    merkle::MerkleProof,
+    state::{
+        BatchId, CompleteTrade, FeeTier, FundingPayment, MarketLimits, MarketParams, MarketStatus, RelayerStatus,
+        StatsTracking,
+    },
+    utils::{
+        calculate_batch_hash, calculate_batch_totals, index_trade_markets, index_trade_referrers,
+        index_trade_user_markets, index_trade_wallets, parse_batch_id,
+    },
+};
 
+// 指令每次只反序列化一个，variant大小差异无影响
+#[allow(clippy::large_enum_variant)]
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub enum SettlementInstruction {
     /// 初始化用户的Settlement账户（首次交易时）
-    /// 
+    ///
     /// Accounts:
     /// 0. `[writable]` UserSettlement PDA - 将被创建
     /// 1. `[signer, writable]` Authority (Relayer) - 支付租金
     /// 2. `[]` System Program
+    /// 3. `[]` ProgramConfig PDA
+    /// 4. `[writable]` GlobalStats PDA - 累加total_users
     InitializeUser {
         /// 用户钱包地址
         wallet: Pubkey,
     },
-    
+
     /// 记录Settlement并更新用户统计
-    /// 
+    ///
+    /// batch_id必须是合法UUID（否则返回InvalidBatchId），同一batch只能记录一次，
+    /// 重复提交返回AccountAlreadyExists。total_volume_e6/total_fees_e6/data_hash
+    /// 会在链上重新计算，不一致时分别返回InvalidTotalVolume/InvalidTotalFees/InvalidDataHash。
+    /// trades的engine_seq必须接续config.last_engine_seq严格递增，否则返回
+    /// SequenceReplay/SequenceGap。
+    ///
     /// Accounts:
-    /// 0..N. `[writable]` UserSettlement PDAs (所有涉及的用户)
-    /// N+1. `[signer]` Authority (Relayer)
-    /// 
-    /// 注意：accounts顺序必须是先taker，后maker，按trades顺序排列
+    /// 0. `[signer, writable]` Authority (Relayer) - 支付SettlementBatch和新UserMarketStats租金
+    /// 1. `[writable]` ProgramConfig PDA - 更新relayer的last_used_at
+    /// 2. `[writable]` SettlementBatch PDA - 将被创建
+    /// 3. `[]` System Program
+    /// 4. `[writable]` GlobalStats PDA
+    /// 5. `[]` FeeSchedule PDA - 尚未设置时按trade自带的费率结算
+    /// 6. `[writable]` UserSettlement PDAs (从此处起，每个涉及的用户一个)
+    /// 7. `[]` Market PDAs (紧接UserSettlement之后，每个涉及的市场一个)
+    /// 8. `[writable]` UserMarketStats PDAs (紧接Market之后，每个涉及的(用户, 市场)组合一个，
+    ///    不存在时创建；config.stats_tracking.skip_user_market_stats时不传)
+    /// 9. `[writable]` MarketStats PDAs (紧接UserMarketStats之后，与Market顺序相同；
+    ///    config.stats_tracking.skip_market_stats时不传)
+    /// 10. `[writable]` ReferrerStats PDAs (紧接MarketStats之后，batch中taker的每个推荐人一个)
+    ///
+    /// 注意：每个UserSettlement只传一次，按wallet在trades中首次出现的顺序排列
+    /// （同一trade内先taker后maker），见utils::index_trade_wallets；
+    /// Market同样按首次出现顺序排列，见utils::index_trade_markets；
+    /// UserMarketStats见utils::index_trade_user_markets；ReferrerStats见utils::index_trade_referrers。
+    /// 有推荐人的taker，其taker手续费按config.referral_share_bp计入推荐人的ReferrerStats。
+    /// trade的市场未登记时返回UnknownMarket，已下架时返回MarketDelisted；
+    /// 不满足市场tick/lot/最大杠杆限制时返回InvalidTrade。
+    /// taker/maker的费率须与其最近30天成交量（该trade之前，含同batch中更早的trade）对应的
+    /// FeeSchedule档位一致，手续费误差不超过tolerance_e6，否则返回FeeMismatch。
     RecordSettlement {
-        /// Batch ID（用于日志）
-        batch_id: String,
+        /// Batch ID（决定SettlementBatch PDA）
+        batch_id: BatchId,
         /// 完整的trade列表
         trades: Vec<CompleteTrade>,
+        /// 总成交额：Σ notional_e6（按市场参数校验）
+        total_volume_e6: UsdcE6,
+        /// 总手续费：Σ (taker_fee_e6 + maker_fee_e6)
+        total_fees_e6: UsdcE6,
+        /// sha256(borsh(trades))
+        data_hash: [u8; 32],
+    },
+
+    /// 初始化全局配置（部署后调用一次，签名者成为admin）
+    ///
+    /// 签名者必须是program的upgrade authority，否则返回UnauthorizedAdmin，
+    /// 防止部署后被他人抢先初始化。
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA - 将被创建
+    /// 1. `[signer, writable]` Admin（upgrade authority） - 支付租金
+    /// 2. `[]` System Program
+    /// 3. `[writable]` GlobalStats PDA - 将被创建
+    /// 4. `[]` ProgramData account（BPF Upgradeable Loader）
+    InitializeConfig {
+        /// 初始授权的relayer列表（Active，label为空）
+        relayers: Vec<Pubkey>,
+    },
+
+    /// 登记新的relayer（仅admin）
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    AddRelayer {
+        /// Relayer公钥
+        relayer: Pubkey,
+        /// 备注（最多32字节）
+        label: String,
+    },
+
+    /// 移除relayer（仅admin）
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    RemoveRelayer {
+        /// Relayer公钥
+        relayer: Pubkey,
+    },
+
+    /// 启用/停用relayer（仅admin）
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    SetRelayerStatus {
+        /// Relayer公钥
+        relayer: Pubkey,
+        /// 新状态
+        status: RelayerStatus,
+    },
+
+    /// 提名新的admin（仅当前admin，需新admin调用AcceptAdmin后生效）
+    ///
+    /// 重复调用会覆盖之前的提名；提名当前admin即撤销提名。
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    ProposeAdmin {
+        /// 新admin公钥
+        new_admin: Pubkey,
+    },
+
+    /// 接受admin权限（必须由pending admin签名）
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Pending Admin
+    AcceptAdmin,
+
+    /// 暂停（仅admin）：market为None时全局暂停，否则只暂停该市场
+    ///
+    /// 全局暂停期间所有非admin的写指令（InitializeUser、RecordSettlement、RecordFunding、
+    /// SetReferrer）被拒绝，admin指令和VerifyTradeInclusion不受影响。
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    Pause {
+        /// 市场ID（None = 全局）
+        market: Option<u16>,
+    },
+
+    /// 恢复（仅admin）：market为None时解除全局暂停，否则只恢复该市场
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    Unpause {
+        /// 市场ID（None = 全局）
+        market: Option<u16>,
+    },
+
+    /// 验证trade包含在已记录的batch中（只读，可被其他program CPI调用）
+    ///
+    /// 验证失败返回InvalidMerkleProof。
+    ///
+    /// Accounts:
+    /// 0. `[]` SettlementBatch PDA
+    VerifyTradeInclusion {
+        /// 待证明的trade
+        trade: CompleteTrade,
+        /// Inclusion proof（由merkle::MerkleTree生成）
+        proof: MerkleProof,
+    },
+
+    /// 设置是否允许engine_seq跳号（仅admin）
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    SetAllowSequenceGaps {
+        /// true = 允许跳号（仍要求严格递增）
+        allow_gaps: bool,
+    },
+
+    /// 登记新市场（仅admin），market_id按登记顺序分配
+    ///
+    /// 重复登记返回AccountAlreadyExists。
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer, writable]` Admin - 支付租金
+    /// 2. `[writable]` Market PDA - 将被创建
+    /// 3. `[]` System Program
+    /// 4. `[writable]` MarketStats PDA - 将被创建
+    RegisterMarket {
+        /// 市场名称（与trade.market一致，最多16字节）
+        symbol: String,
+        /// 合约参数（登记后不可修改）
+        params: MarketParams,
+        /// 交易限制
+        limits: MarketLimits,
+    },
+
+    /// 修改市场的tick/lot/最大杠杆（仅admin）
+    ///
+    /// Accounts:
+    /// 0. `[]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    /// 2. `[writable]` Market PDA
+    UpdateMarketLimits {
+        /// 市场名称
+        symbol: String,
+        /// 新的交易限制
+        limits: MarketLimits,
+    },
+
+    /// 上架/下架市场（仅admin），已下架市场的trades返回MarketDelisted
+    ///
+    /// Accounts:
+    /// 0. `[]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    /// 2. `[writable]` Market PDA
+    SetMarketStatus {
+        /// 市场名称
+        symbol: String,
+        /// 新状态
+        status: MarketStatus,
+    },
+
+    /// 设置手续费档位表（仅admin），FeeSchedule不存在时创建
+    ///
+    /// 档位不合法时返回InvalidFeeSchedule，见FeeSchedule::validate_tiers。
+    ///
+    /// Accounts:
+    /// 0. `[]` ProgramConfig PDA
+    /// 1. `[signer, writable]` Admin - 首次设置时支付租金
+    /// 2. `[writable]` FeeSchedule PDA
+    /// 3. `[]` System Program
+    SetFeeSchedule {
+        /// 费率档位（按min_volume_e6严格递增）
+        tiers: Vec<FeeTier>,
+        /// 手续费允许的取整误差（USDC, e6）
+        tolerance_e6: u64,
+    },
+
+    /// 设置用户的推荐人（用户签名，只能设置一次）
+    ///
+    /// 推荐人为空、为用户自己或用户已有推荐人时返回InvalidReferrer。
+    /// 推荐人的ReferrerStats不存在时创建；旧版本UserSettlement先扩容迁移。
+    ///
+    /// Accounts:
+    /// 0. `[signer, writable]` 用户钱包 - 支付租金
+    /// 1. `[writable]` UserSettlement PDA
+    /// 2. `[writable]` ReferrerStats PDA
+    /// 3. `[]` System Program
+    /// 4. `[]` ProgramConfig PDA - 全局暂停时拒绝
+    SetReferrer {
+        /// 推荐人钱包地址
+        referrer: Pubkey,
+    },
+
+    /// 设置推荐人分成比例（仅admin）
+    ///
+    /// 超过ProgramConfig::MAX_REFERRAL_SHARE_BP时返回InvalidArgument。
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    SetReferralShare {
+        /// 被推荐用户taker手续费的分成比例（bp，1/10_000）
+        share_bp: u16,
+    },
+
+    /// 记录一个市场一个周期的资金费并更新用户的累计资金费
+    ///
+    /// 同一市场同一周期只能记录一次，重复提交返回AccountAlreadyExists。
+    /// payments为空或wallet重复时返回InvalidFunding；
+    /// |Σ amount_e6| 超过config.funding_tolerance_e6时返回FundingImbalance。
+    /// 每个账户的资金费由链下按资金费率和持仓计算，链上只校验净额。
+    ///
+    /// Accounts:
+    /// 0. `[signer, writable]` Authority (Relayer) - 支付FundingEpoch租金
+    /// 1. `[writable]` ProgramConfig PDA - 更新relayer的last_used_at
+    /// 2. `[writable]` FundingEpoch PDA - 将被创建
+    /// 3. `[]` System Program
+    /// 4. `[]` Market PDA
+    /// 5. `[writable]` UserSettlement PDAs (从此处起，与payments顺序相同)
+    RecordFunding {
+        /// 资金费周期ID
+        epoch_id: u64,
+        /// 市场名称
+        market: String,
+        /// 每个账户的资金费（正数 = 支付，负数 = 收取）
+        payments: Vec<FundingPayment>,
+    },
+
+    /// 设置RecordFunding允许的净额误差（仅admin）
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    SetFundingTolerance {
+        /// 允许的净额误差（USDC, e6）
+        tolerance_e6: u64,
+    },
+
+    /// 设置RecordSettlement跳过的可选统计（仅admin）
+    ///
+    /// 跳过的统计不再需要传入对应的accounts，单笔交易可以容纳更多trades；
+    /// 跳过期间的trades不计入对应统计（包括UserMarketStats中的持仓和已实现盈亏）。
+    ///
+    /// Accounts:
+    /// 0. `[writable]` ProgramConfig PDA
+    /// 1. `[signer]` Admin
+    SetStatsTracking {
+        /// 跳过的统计
+        stats_tracking: StatsTracking,
     },
 }
 
+/// 派生ProgramConfig PDA
+pub fn find_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config"], program_id)
+}
+
+/// 派生program的ProgramData account地址（BPF Upgradeable Loader）
+pub fn find_program_data_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id())
+}
+
+/// 派生UserSettlement PDA
+pub fn find_user_settlement_address(wallet: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"user_settlement", wallet.as_ref()], program_id)
+}
+
+/// 派生Market PDA
+pub fn find_market_address(symbol: &str, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"market", symbol.as_bytes()], program_id)
+}
+
+/// 派生GlobalStats PDA
+pub fn find_global_stats_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"global_stats"], program_id)
+}
+
+/// 派生FeeSchedule PDA
+pub fn find_fee_schedule_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"fee_schedule"], program_id)
+}
+
+/// 派生MarketStats PDA
+pub fn find_market_stats_address(symbol: &str, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"market_stats", symbol.as_bytes()], program_id)
+}
+
+/// 派生UserMarketStats PDA（market_id按little-endian编码）
+pub fn find_user_market_stats_address(
+    wallet: &Pubkey,
+    market_id: u16,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"user_market", wallet.as_ref(), &market_id.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// 派生ReferrerStats PDA
+pub fn find_referrer_stats_address(referrer: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"referrer_stats", referrer.as_ref()], program_id)
+}
+
+/// 派生FundingEpoch PDA（epoch_id按little-endian编码）
+pub fn find_funding_epoch_address(symbol: &str, epoch_id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"funding", symbol.as_bytes(), &epoch_id.to_le_bytes()], program_id)
+}
+
+/// 派生SettlementBatch PDA
+pub fn find_settlement_batch_address(batch_id: &[u8; 16], program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"settlement", batch_id], program_id)
+}
+
+/// 构造InitializeUser指令
+pub fn initialize_user(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    wallet: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::InitializeUser { wallet: *wallet }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(find_user_settlement_address(wallet, program_id).0, false),
+            AccountMeta::new(*authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(find_global_stats_address(program_id).0, false),
+        ],
+        data,
+    })
+}
+
+/// 构造RecordSettlement指令（total_volume_e6/total_fees_e6/data_hash由trades计算）
+///
+/// market_ids为已登记市场的(symbol, market_id)，用于派生UserMarketStats PDA；
+/// trades中的市场不在其中时返回UnknownMarket。
+/// trades中的用户都没有推荐人；否则使用 `record_settlement_with_referrers`。
+pub fn record_settlement(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    batch_id: BatchId,
+    trades: Vec<CompleteTrade>,
+    market_ids: &[(&str, u16)],
+) -> Result<Instruction, ProgramError> {
+    record_settlement_with_referrers(program_id, authority, batch_id, trades, market_ids, &[])
+}
+
+/// 构造RecordSettlement指令，referrers为已设置推荐人的用户的(wallet, referrer)
+///
+/// 必须包含trades中所有有推荐人的taker（与链上UserSettlement.referrer一致），
+/// 否则ReferrerStats accounts与链上不符，交易失败。
+/// 按默认的config.stats_tracking（全部更新）传入accounts；否则使用 `record_settlement_with_stats`。
+pub fn record_settlement_with_referrers(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    batch_id: BatchId,
+    trades: Vec<CompleteTrade>,
+    market_ids: &[(&str, u16)],
+    referrers: &[(Pubkey, Pubkey)],
+) -> Result<Instruction, ProgramError> {
+    record_settlement_with_stats(
+        program_id,
+        authority,
+        batch_id,
+        trades,
+        market_ids,
+        referrers,
+        StatsTracking::ALL,
+    )
+}
+
+/// 构造RecordSettlement指令，stats_tracking须与链上config.stats_tracking一致
+///
+/// 跳过的统计不传对应的UserMarketStats/MarketStats accounts。
+pub fn record_settlement_with_stats(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    batch_id: BatchId,
+    trades: Vec<CompleteTrade>,
+    market_ids: &[(&str, u16)],
+    referrers: &[(Pubkey, Pubkey)],
+    stats_tracking: StatsTracking,
+) -> Result<Instruction, ProgramError> {
+    let batch_bytes = parse_batch_id(&batch_id)?;
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(find_config_address(program_id).0, false),
+        AccountMeta::new(find_settlement_batch_address(&batch_bytes, program_id).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new(find_global_stats_address(program_id).0, false),
+        AccountMeta::new_readonly(find_fee_schedule_address(program_id).0, false),
+    ];
+    let (wallets, trade_user_indices) = index_trade_wallets(&trades);
+    for wallet in &wallets {
+        accounts.push(AccountMeta::new(
+            find_user_settlement_address(wallet, program_id).0,
+            false,
+        ));
+    }
+    let (symbols, trade_market_indices) = index_trade_markets(&trades);
+    for symbol in &symbols {
+        accounts.push(AccountMeta::new_readonly(
+            find_market_address(symbol, program_id).0,
+            false,
+        ));
+    }
+    let symbol_ids = symbols.iter()
+        .map(|symbol| {
+            market_ids.iter()
+                .find(|(s, _)| s == symbol)
+                .map(|(_, id)| *id)
+                .ok_or(SettlementError::UnknownMarket)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if !stats_tracking.skip_user_market_stats {
+        let (user_markets, _) = index_trade_user_markets(&trade_user_indices, &trade_market_indices);
+        for (user_idx, market_idx) in user_markets {
+            accounts.push(AccountMeta::new(
+                find_user_market_stats_address(&wallets[user_idx], symbol_ids[market_idx], program_id).0,
+                false,
+            ));
+        }
+    }
+    if !stats_tracking.skip_market_stats {
+        for symbol in &symbols {
+            accounts.push(AccountMeta::new(find_market_stats_address(symbol, program_id).0, false));
+        }
+    }
+    let user_referrers = wallets.iter()
+        .map(|wallet| referrers.iter().find(|(w, _)| w == wallet).map(|(_, referrer)| *referrer))
+        .collect::<Vec<_>>();
+    let (batch_referrers, _) = index_trade_referrers(&user_referrers, &trade_user_indices);
+    for referrer in &batch_referrers {
+        accounts.push(AccountMeta::new(find_referrer_stats_address(referrer, program_id).0, false));
+    }
+
+    let (total_volume_e6, total_fees_e6) = calculate_batch_totals(&trades)?;
+    let data_hash = calculate_batch_hash(&trades)?;
+
+    let data = SettlementInstruction::RecordSettlement {
+        batch_id,
+        trades,
+        total_volume_e6,
+        total_fees_e6,
+        data_hash,
+    }
+    .try_to_vec()
+    .map_err(SettlementError::from)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// RecordSettlement中SettlementBatch PDA的位置（每个batch不同，不放入lookup table）
+const RECORD_SETTLEMENT_BATCH_ACCOUNT_INDEX: usize = 2;
+
+/// RecordSettlement中可以放入Address Lookup Table的accounts
+///
+/// 即除relayer（签名者）和每个batch新建的SettlementBatch PDA之外的所有accounts。
+/// 它们在batch之间复用，relayer应在InitializeUser / RegisterMarket之后把新的account
+/// 加入长期使用的lookup table（`address_lookup_table::instruction::extend_lookup_table`，
+/// 下一个slot起可用），而不是为每个batch新建table。
+pub fn record_settlement_lookup_table_addresses(instruction: &Instruction) -> Vec<Pubkey> {
+    instruction.accounts.iter()
+        .enumerate()
+        .filter(|(i, meta)| !meta.is_signer && *i != RECORD_SETTLEMENT_BATCH_ACCOUNT_INDEX)
+        .map(|(_, meta)| meta.pubkey)
+        .collect()
+}
+
+/// 用Address Lookup Table编译RecordSettlement交易的v0 message（推荐的提交方式）
+///
+/// legacy交易中每个account占32字节，RecordSettlement只能容纳1个trade；lookup table中的account
+/// 只占1字节索引，可以容纳与1.0.0相同的2个trade（trade字段较短时更多），见docs/COMPUTE_UNITS.md。
+/// 不在lookup_tables中的account仍以32字节静态地址编码。payer通常是relayer本身。
+pub fn record_settlement_v0_message(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+) -> Result<v0::Message, ProgramError> {
+    v0::Message::try_compile(payer, instructions, lookup_tables, recent_blockhash)
+        .map_err(|_| ProgramError::InvalidArgument)
+}
+
+/// 构造InitializeConfig指令
+pub fn initialize_config(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    relayers: Vec<Pubkey>,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::InitializeConfig { relayers }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(find_config_address(program_id).0, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(find_global_stats_address(program_id).0, false),
+            AccountMeta::new_readonly(find_program_data_address(program_id).0, false),
+        ],
+        data,
+    })
+}
+
+/// 构造AddRelayer指令
+pub fn add_relayer(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    relayer: &Pubkey,
+    label: String,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::AddRelayer { relayer: *relayer, label }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, admin, data))
+}
+
+/// 构造RemoveRelayer指令
+pub fn remove_relayer(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    relayer: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::RemoveRelayer { relayer: *relayer }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, admin, data))
+}
+
+/// 构造SetRelayerStatus指令
+pub fn set_relayer_status(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    relayer: &Pubkey,
+    status: RelayerStatus,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::SetRelayerStatus { relayer: *relayer, status }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, admin, data))
+}
+
+/// 构造ProposeAdmin指令
+pub fn propose_admin(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    new_admin: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::ProposeAdmin { new_admin: *new_admin }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, admin, data))
+}
+
+/// 构造AcceptAdmin指令
+pub fn accept_admin(
+    program_id: &Pubkey,
+    pending_admin: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::AcceptAdmin
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, pending_admin, data))
+}
+
+/// 构造Pause指令
+pub fn pause(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    market: Option<u16>,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::Pause { market }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, admin, data))
+}
+
+/// 构造Unpause指令
+pub fn unpause(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    market: Option<u16>,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::Unpause { market }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, admin, data))
+}
+
+/// 构造SetAllowSequenceGaps指令
+pub fn set_allow_sequence_gaps(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    allow_gaps: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::SetAllowSequenceGaps { allow_gaps }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, admin, data))
+}
+
+/// 构造RegisterMarket指令
+pub fn register_market(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    symbol: &str,
+    params: MarketParams,
+    limits: MarketLimits,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::RegisterMarket { symbol: symbol.to_string(), params, limits }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(find_config_address(program_id).0, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(find_market_address(symbol, program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(find_market_stats_address(symbol, program_id).0, false),
+        ],
+        data,
+    })
+}
+
+/// 构造UpdateMarketLimits指令
+pub fn update_market_limits(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    symbol: &str,
+    limits: MarketLimits,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::UpdateMarketLimits { symbol: symbol.to_string(), limits }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(market_admin_instruction(program_id, admin, symbol, data))
+}
+
+/// 构造SetMarketStatus指令
+pub fn set_market_status(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    symbol: &str,
+    status: MarketStatus,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::SetMarketStatus { symbol: symbol.to_string(), status }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(market_admin_instruction(program_id, admin, symbol, data))
+}
+
+/// 构造SetFeeSchedule指令
+pub fn set_fee_schedule(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    tiers: Vec<FeeTier>,
+    tolerance_e6: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::SetFeeSchedule { tiers, tolerance_e6 }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(find_fee_schedule_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+/// 构造SetReferrer指令（wallet签名）
+pub fn set_referrer(
+    program_id: &Pubkey,
+    wallet: &Pubkey,
+    referrer: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::SetReferrer { referrer: *referrer }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*wallet, true),
+            AccountMeta::new(find_user_settlement_address(wallet, program_id).0, false),
+            AccountMeta::new(find_referrer_stats_address(referrer, program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+        data,
+    })
+}
+
+/// 构造SetReferralShare指令
+pub fn set_referral_share(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    share_bp: u16,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::SetReferralShare { share_bp }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, admin, data))
+}
+
+/// 构造RecordFunding指令
+pub fn record_funding(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    epoch_id: u64,
+    market: &str,
+    payments: Vec<FundingPayment>,
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(find_config_address(program_id).0, false),
+        AccountMeta::new(find_funding_epoch_address(market, epoch_id, program_id).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(find_market_address(market, program_id).0, false),
+    ];
+    for payment in &payments {
+        accounts.push(AccountMeta::new(
+            find_user_settlement_address(&payment.wallet, program_id).0,
+            false,
+        ));
+    }
+
+    let data = SettlementInstruction::RecordFunding {
+        epoch_id,
+        market: market.to_string(),
+        payments,
+    }
+    .try_to_vec()
+    .map_err(SettlementError::from)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// 构造SetFundingTolerance指令
+pub fn set_funding_tolerance(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    tolerance_e6: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::SetFundingTolerance { tolerance_e6 }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, admin, data))
+}
+
+/// 构造SetStatsTracking指令
+pub fn set_stats_tracking(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    stats_tracking: StatsTracking,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::SetStatsTracking { stats_tracking }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(admin_instruction(program_id, admin, data))
+}
+
+/// 构造VerifyTradeInclusion指令
+pub fn verify_trade_inclusion(
+    program_id: &Pubkey,
+    batch_id: &[u8; 16],
+    trade: CompleteTrade,
+    proof: MerkleProof,
+) -> Result<Instruction, ProgramError> {
+    let data = SettlementInstruction::VerifyTradeInclusion { trade, proof }
+        .try_to_vec()
+        .map_err(SettlementError::from)?;
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: vec![AccountMeta::new_readonly(
+            find_settlement_batch_address(batch_id, program_id).0,
+            false,
+        )],
+        data,
+    })
+}
+
+/// Admin指令的通用account布局：config + admin签名
+fn admin_instruction(program_id: &Pubkey, admin: &Pubkey, data: Vec<u8>) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(find_config_address(program_id).0, false),
+            AccountMeta::new_readonly(*admin, true),
+        ],
+        data,
+    }
+}
+
+/// 修改Market的admin指令：config + admin签名 + market
+fn market_admin_instruction(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    symbol: &str,
+    data: Vec<u8>,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(find_market_address(symbol, program_id).0, false),
+        ],
+        data,
+    }
+}
@@ -13,8 +13,10 @@ use solana_program::{
     account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, pubkey::Pubkey,
 };
 
+pub mod amount;
 pub mod error;
 pub mod instruction;
+pub mod merkle;
 pub mod processor;
 pub mod state;
 pub mod utils;
@@ -37,9 +39,13 @@ pub fn process_instruction(
 }
 
 // 导出公共类型，方便客户端使用
+pub use amount::{PriceE6, QtyE6, Rounding, UsdcE6, WideUsdcE6};
 pub use error::SettlementError;
 pub use instruction::SettlementInstruction;
 pub use state::{
-    CompleteTrade, UserSettlement, SettlementSummary, Side,
+    BatchId, CompleteTrade, FeeSchedule, FeeTier, FundingEpoch, FundingPayment, GlobalStats,
+    LegacyUserSettlement, Market, MarketLimits, MarketParams, MarketStats, MarketStatus, ProgramConfig,
+    ReferrerStats, RelayerInfo, RelayerStatus, SettlementBatch, UserMarketStats, UserSettlement,
+    SettlementSummary, Side, StatsTracking,
 };
 
@@ -0,0 +1,245 @@
+//! Settlement Batch Merkle Tree
+//!
+//! 每个batch的trades构成一棵Merkle树，root保存在SettlementBatch中。
+//! 用户只需持有自己的trade和inclusion proof，即可证明该trade已被结算，无需重放整个batch。
+//!
+//! 构造规则（链上链下必须一致）：
+//! - leaf = sha256(0x00 || borsh(trade))
+//! - node = sha256(0x01 || left || right)
+//! - 某一层节点数为奇数时，最后一个节点原样提升到上一层（不复制，避免重复leaf产生相同root）
+//! - 空树的root为全0
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+
+use crate::{error::SettlementError, state::CompleteTrade};
+
+/// Leaf hash前缀（与内部节点区分，防止second-preimage攻击）
+const LEAF_PREFIX: u8 = 0x00;
+
+/// 内部节点hash前缀
+const NODE_PREFIX: u8 = 0x01;
+
+/// Trade inclusion proof
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// trade在batch中的位置
+    pub leaf_index: u32,
+    /// batch中的trade总数
+    pub leaf_count: u32,
+    /// 自底向上的兄弟节点（被提升的层没有兄弟节点）
+    pub siblings: Vec<[u8; 32]>,
+}
+
+/// 计算trade的leaf hash
+pub fn hash_trade(trade: &CompleteTrade) -> Result<[u8; 32], SettlementError> {
+    let encoded = trade.try_to_vec()?;
+    Ok(Sha256::new()
+        .chain_update([LEAF_PREFIX])
+        .chain_update(&encoded)
+        .finalize()
+        .into())
+}
+
+/// 计算内部节点hash
+pub fn hash_nodes(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update([NODE_PREFIX])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+/// 计算一组leaf的Merkle root（链上使用，不保留中间层）
+pub fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return [0u8; 32];
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// 计算trades的Merkle root
+pub fn trades_merkle_root(trades: &[CompleteTrade]) -> Result<[u8; 32], SettlementError> {
+    let leaves = trades.iter()
+        .map(hash_trade)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(merkle_root(&leaves))
+}
+
+/// 验证inclusion proof
+pub fn verify_proof(root: &[u8; 32], leaf: &[u8; 32], proof: &MerkleProof) -> bool {
+    if proof.leaf_index >= proof.leaf_count {
+        return false;
+    }
+
+    let mut siblings = proof.siblings.iter();
+    let mut hash = *leaf;
+    let mut index = proof.leaf_index;
+    let mut count = proof.leaf_count;
+
+    while count > 1 {
+        if index % 2 == 1 {
+            match siblings.next() {
+                Some(sibling) => hash = hash_nodes(sibling, &hash),
+                None => return false,
+            }
+        } else if index + 1 < count {
+            match siblings.next() {
+                Some(sibling) => hash = hash_nodes(&hash, sibling),
+                None => return false,
+            }
+        }
+        index /= 2;
+        count = count.div_ceil(2);
+    }
+
+    siblings.next().is_none() && &hash == root
+}
+
+/// 完整Merkle树（链下使用，用于生成proof）
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    /// levels[0]为leaves，最后一层为root
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    /// 由leaf hashes构造
+    pub fn new(leaves: Vec<[u8; 32]>) -> Self {
+        let mut levels = vec![leaves];
+        while levels.last().map(|l| l.len() > 1).unwrap_or(false) {
+            let next = next_level(levels.last().unwrap());
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    /// 由trades构造
+    pub fn from_trades(trades: &[CompleteTrade]) -> Result<Self, SettlementError> {
+        let leaves = trades.iter()
+            .map(hash_trade)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(leaves))
+    }
+
+    /// Merkle root（空树为全0）
+    pub fn root(&self) -> [u8; 32] {
+        self.levels.last()
+            .and_then(|level| level.first().copied())
+            .unwrap_or([0u8; 32])
+    }
+
+    /// 生成第leaf_index个leaf的inclusion proof
+    pub fn proof(&self, leaf_index: usize) -> Option<MerkleProof> {
+        let leaf_count = self.levels[0].len();
+        if leaf_index >= leaf_count {
+            return None;
+        }
+
+        let mut siblings = Vec::new();
+        let mut index = leaf_index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if sibling < level.len() {
+                siblings.push(level[sibling]);
+            }
+            index /= 2;
+        }
+
+        Some(MerkleProof {
+            leaf_index: leaf_index as u32,
+            leaf_count: leaf_count as u32,
+            siblings,
+        })
+    }
+}
+
+/// 两两合并得到上一层，奇数个时最后一个节点原样提升
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level.chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_nodes(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: u8) -> Vec<[u8; 32]> {
+        (0..n).map(|i| [i; 32]).collect()
+    }
+
+    #[test]
+    fn test_root_matches_tree() {
+        for n in 0..10 {
+            let leaves = leaves(n);
+            assert_eq!(merkle_root(&leaves), MerkleTree::new(leaves).root());
+        }
+    }
+
+    #[test]
+    fn test_single_leaf_root_is_leaf() {
+        let leaves = leaves(1);
+        assert_eq!(merkle_root(&leaves), leaves[0]);
+    }
+
+    #[test]
+    fn test_proofs_verify_for_every_leaf() {
+        for n in 1..10 {
+            let leaves = leaves(n);
+            let tree = MerkleTree::new(leaves.clone());
+            let root = tree.root();
+
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(i).unwrap();
+                assert!(verify_proof(&root, leaf, &proof), "n={} i={}", n, i);
+            }
+            assert!(tree.proof(n as usize).is_none());
+        }
+    }
+
+    #[test]
+    fn test_tampered_proof_fails() {
+        let leaves = leaves(5);
+        let tree = MerkleTree::new(leaves.clone());
+        let root = tree.root();
+        let proof = tree.proof(2).unwrap();
+
+        // 错误的leaf
+        assert!(!verify_proof(&root, &leaves[3], &proof));
+
+        // 错误的位置
+        let mut wrong_index = proof.clone();
+        wrong_index.leaf_index = 3;
+        assert!(!verify_proof(&root, &leaves[2], &wrong_index));
+
+        // 多余的兄弟节点
+        let mut extra = proof.clone();
+        extra.siblings.push([9; 32]);
+        assert!(!verify_proof(&root, &leaves[2], &extra));
+
+        // 越界
+        let mut out_of_range = proof;
+        out_of_range.leaf_index = 5;
+        assert!(!verify_proof(&root, &leaves[2], &out_of_range));
+    }
+
+    #[test]
+    fn test_odd_leaf_is_not_duplicated() {
+        // [a, b, c] 与 [a, b, c, c] 的root不同
+        let three = leaves(3);
+        let mut four = three.clone();
+        four.push(three[2]);
+        assert_ne!(merkle_root(&three), merkle_root(&four));
+    }
+}
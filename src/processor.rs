@@ -3,25 +3,36 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
     entrypoint::ProgramResult,
     msg,
-    program::invoke_signed,
+    program::{invoke, invoke_signed},
     program_error::ProgramError,
+    program_utils::limited_deserialize,
     pubkey::Pubkey,
     rent::Rent,
+    system_instruction,
     sysvar::Sysvar,
 };
 
 use crate::{
+    amount::UsdcE6,
     error::SettlementError,
     instruction::SettlementInstruction,
-    state::{CompleteTrade, UserSettlement},
-    utils::validate_settlement_data,
+    merkle::{hash_trade, trades_merkle_root, verify_proof, MerkleProof},
+    state::{
+        BatchId, CompleteTrade, FeeSchedule, FeeTier, FundingEpoch, FundingPayment, GlobalStats,
+        LegacyUserSettlement, Market, MarketLimits, MarketParams, MarketStats, MarketStatus, ProgramConfig,
+        ReferrerStats, RelayerInfo, RelayerStatus, SettlementBatch, StatsTracking, UserMarketStats,
+        UserSettlement,
+    },
+    utils::{
+        format_batch_id, index_trade_markets, index_trade_referrers, index_trade_user_markets, index_trade_wallets,
+        parse_batch_id, validate_engine_sequence,
+        validate_settlement_data, verify_batch_commitment,
+    },
 };
 
-/// 授权的Relayer公钥（临时硬编码，未来从Config读取）
-pub const AUTHORIZED_RELAYER: &str = "J1Szw8HZYL95NvYUsNhg3e6NzKQLUZ9UxQsKg4hsQnad";
-
 pub fn process_instruction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
@@ -30,16 +41,481 @@ pub fn process_instruction(
     let instruction = SettlementInstruction::try_from_slice(instruction_data)
         .map_err(|_| ProgramError::InvalidInstructionData)?;
     
+    // 全局暂停统一在此检查，各handler只检查市场级暂停
+    if let Some(config_index) = pause_gated_config_index(&instruction) {
+        let config_account = accounts.get(config_index).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        check_not_paused(&load_config(program_id, config_account)?)?;
+    }
+    
     match instruction {
         SettlementInstruction::InitializeUser { wallet } => {
             msg!("Instruction: InitializeUser");
             process_initialize_user(program_id, accounts, wallet)
         }
-        SettlementInstruction::RecordSettlement { batch_id, trades } => {
+        SettlementInstruction::RecordSettlement {
+            batch_id,
+            trades,
+            total_volume_e6,
+            total_fees_e6,
+            data_hash,
+        } => {
             msg!("Instruction: RecordSettlement");
-            process_record_settlement(program_id, accounts, batch_id, trades)
+            process_record_settlement(
+                program_id,
+                accounts,
+                batch_id,
+                trades,
+                total_volume_e6,
+                total_fees_e6,
+                data_hash,
+            )
+        }
+        SettlementInstruction::InitializeConfig { relayers } => {
+            msg!("Instruction: InitializeConfig");
+            process_initialize_config(program_id, accounts, relayers)
+        }
+        SettlementInstruction::AddRelayer { relayer, label } => {
+            msg!("Instruction: AddRelayer");
+            process_add_relayer(program_id, accounts, relayer, label)
+        }
+        SettlementInstruction::RemoveRelayer { relayer } => {
+            msg!("Instruction: RemoveRelayer");
+            process_remove_relayer(program_id, accounts, relayer)
+        }
+        SettlementInstruction::SetRelayerStatus { relayer, status } => {
+            msg!("Instruction: SetRelayerStatus");
+            process_set_relayer_status(program_id, accounts, relayer, status)
+        }
+        SettlementInstruction::ProposeAdmin { new_admin } => {
+            msg!("Instruction: ProposeAdmin");
+            process_propose_admin(program_id, accounts, new_admin)
+        }
+        SettlementInstruction::AcceptAdmin => {
+            msg!("Instruction: AcceptAdmin");
+            process_accept_admin(program_id, accounts)
+        }
+        SettlementInstruction::Pause { market } => {
+            msg!("Instruction: Pause");
+            process_set_paused(program_id, accounts, market, true)
+        }
+        SettlementInstruction::Unpause { market } => {
+            msg!("Instruction: Unpause");
+            process_set_paused(program_id, accounts, market, false)
+        }
+        SettlementInstruction::VerifyTradeInclusion { trade, proof } => {
+            msg!("Instruction: VerifyTradeInclusion");
+            process_verify_trade_inclusion(program_id, accounts, trade, proof)
+        }
+        SettlementInstruction::SetAllowSequenceGaps { allow_gaps } => {
+            msg!("Instruction: SetAllowSequenceGaps");
+            process_set_allow_sequence_gaps(program_id, accounts, allow_gaps)
+        }
+        SettlementInstruction::RegisterMarket { symbol, params, limits } => {
+            msg!("Instruction: RegisterMarket");
+            process_register_market(program_id, accounts, symbol, params, limits)
+        }
+        SettlementInstruction::UpdateMarketLimits { symbol, limits } => {
+            msg!("Instruction: UpdateMarketLimits");
+            process_update_market_limits(program_id, accounts, &symbol, limits)
+        }
+        SettlementInstruction::SetMarketStatus { symbol, status } => {
+            msg!("Instruction: SetMarketStatus");
+            process_set_market_status(program_id, accounts, &symbol, status)
+        }
+        SettlementInstruction::SetFeeSchedule { tiers, tolerance_e6 } => {
+            msg!("Instruction: SetFeeSchedule");
+            process_set_fee_schedule(program_id, accounts, tiers, tolerance_e6)
+        }
+        SettlementInstruction::SetReferrer { referrer } => {
+            msg!("Instruction: SetReferrer");
+            process_set_referrer(program_id, accounts, referrer)
+        }
+        SettlementInstruction::SetReferralShare { share_bp } => {
+            msg!("Instruction: SetReferralShare");
+            process_set_referral_share(program_id, accounts, share_bp)
+        }
+        SettlementInstruction::RecordFunding { epoch_id, market, payments } => {
+            msg!("Instruction: RecordFunding");
+            process_record_funding(program_id, accounts, epoch_id, &market, payments)
+        }
+        SettlementInstruction::SetFundingTolerance { tolerance_e6 } => {
+            msg!("Instruction: SetFundingTolerance");
+            process_set_funding_tolerance(program_id, accounts, tolerance_e6)
+        }
+        SettlementInstruction::SetStatsTracking { stats_tracking } => {
+            msg!("Instruction: SetStatsTracking");
+            process_set_stats_tracking(program_id, accounts, stats_tracking)
+        }
+    }
+}
+
+/// 受全局暂停约束的指令返回其ProgramConfig账户的位置
+///
+/// 所有非admin的写指令都受约束；admin指令（含Pause/Unpause）和只读的
+/// VerifyTradeInclusion不受影响，InitializeConfig时config尚不存在。
+fn pause_gated_config_index(instruction: &SettlementInstruction) -> Option<usize> {
+    match instruction {
+        SettlementInstruction::InitializeUser { .. } => Some(3),
+        SettlementInstruction::RecordSettlement { .. } => Some(1),
+        SettlementInstruction::RecordFunding { .. } => Some(1),
+        SettlementInstruction::SetReferrer { .. } => Some(4),
+        SettlementInstruction::InitializeConfig { .. }
+        | SettlementInstruction::AddRelayer { .. }
+        | SettlementInstruction::RemoveRelayer { .. }
+        | SettlementInstruction::SetRelayerStatus { .. }
+        | SettlementInstruction::ProposeAdmin { .. }
+        | SettlementInstruction::AcceptAdmin
+        | SettlementInstruction::Pause { .. }
+        | SettlementInstruction::Unpause { .. }
+        | SettlementInstruction::VerifyTradeInclusion { .. }
+        | SettlementInstruction::SetAllowSequenceGaps { .. }
+        | SettlementInstruction::RegisterMarket { .. }
+        | SettlementInstruction::UpdateMarketLimits { .. }
+        | SettlementInstruction::SetMarketStatus { .. }
+        | SettlementInstruction::SetFeeSchedule { .. }
+        | SettlementInstruction::SetReferralShare { .. }
+        | SettlementInstruction::SetFundingTolerance { .. }
+        | SettlementInstruction::SetStatsTracking { .. } => None,
+    }
+}
+
+/// 读取并校验ProgramConfig账户
+fn load_config(
+    program_id: &Pubkey,
+    config_account: &AccountInfo,
+) -> Result<ProgramConfig, ProgramError> {
+    if config_account.owner != program_id {
+        msg!("Error: Config account owner mismatch");
+        return Err(SettlementError::InvalidConfigAccount.into());
+    }
+    
+    // Config按最大容量分配，尾部可能是未使用的零字节
+    let config = ProgramConfig::deserialize(&mut &config_account.data.borrow()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    if config.discriminator != ProgramConfig::DISCRIMINATOR {
+        msg!("Error: Config discriminator mismatch");
+        return Err(SettlementError::InvalidConfigAccount.into());
+    }
+    
+    let expected_pda = Pubkey::create_program_address(&[b"config", &[config.bump]], program_id)
+        .map_err(|_| SettlementError::InvalidConfigAccount)?;
+    
+    if config_account.key != &expected_pda {
+        msg!("Error: Config PDA mismatch. Expected {}, got {}",
+            expected_pda, config_account.key);
+        return Err(SettlementError::InvalidConfigAccount.into());
+    }
+    
+    Ok(config)
+}
+
+/// 写回ProgramConfig账户
+fn save_config(config: &ProgramConfig, config_account: &AccountInfo) -> ProgramResult {
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    Ok(())
+}
+
+/// 读取并校验GlobalStats账户
+fn load_global_stats(
+    program_id: &Pubkey,
+    stats_account: &AccountInfo,
+) -> Result<GlobalStats, ProgramError> {
+    if stats_account.owner != program_id {
+        msg!("Error: GlobalStats account owner mismatch");
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    let stats = GlobalStats::deserialize(&mut &stats_account.data.borrow()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    if stats.discriminator != GlobalStats::DISCRIMINATOR {
+        msg!("Error: GlobalStats discriminator mismatch");
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    let expected_pda = Pubkey::create_program_address(&[b"global_stats", &[stats.bump]], program_id)
+        .map_err(|_| SettlementError::InvalidSettlementAccount)?;
+    
+    if stats_account.key != &expected_pda {
+        msg!("Error: GlobalStats PDA mismatch. Expected {}, got {}",
+            expected_pda, stats_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    Ok(stats)
+}
+
+/// 写回GlobalStats账户
+fn save_global_stats(stats: &GlobalStats, stats_account: &AccountInfo) -> ProgramResult {
+    stats.serialize(&mut &mut stats_account.data.borrow_mut()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    Ok(())
+}
+
+/// 读取并校验FeeSchedule账户
+///
+/// 尚未通过SetFeeSchedule设置（PDA不属于本program）时返回None，此时按trade自带的费率结算。
+fn load_fee_schedule(
+    program_id: &Pubkey,
+    fee_schedule_account: &AccountInfo,
+) -> Result<Option<FeeSchedule>, ProgramError> {
+    if fee_schedule_account.owner != program_id {
+        // 必须是FeeSchedule PDA本身，不能用其他账户绕过费率校验
+        let (expected_pda, _) = Pubkey::find_program_address(&[b"fee_schedule"], program_id);
+        if fee_schedule_account.key != &expected_pda {
+            msg!("Error: FeeSchedule PDA mismatch. Expected {}, got {}",
+                expected_pda, fee_schedule_account.key);
+            return Err(SettlementError::InvalidSettlementAccount.into());
+        }
+        
+        msg!("FeeSchedule not set, using per-trade fee rates");
+        return Ok(None);
+    }
+    
+    let fee_schedule = FeeSchedule::deserialize(&mut &fee_schedule_account.data.borrow()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    if fee_schedule.discriminator != FeeSchedule::DISCRIMINATOR {
+        msg!("Error: FeeSchedule discriminator mismatch");
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    let expected_pda = Pubkey::create_program_address(&[b"fee_schedule", &[fee_schedule.bump]], program_id)
+        .map_err(|_| SettlementError::InvalidSettlementAccount)?;
+    
+    if fee_schedule_account.key != &expected_pda {
+        msg!("Error: FeeSchedule PDA mismatch. Expected {}, got {}",
+            expected_pda, fee_schedule_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    Ok(Some(fee_schedule))
+}
+
+/// 验证authority是已签名的授权relayer
+fn check_relayer(config: &ProgramConfig, authority: &AccountInfo) -> ProgramResult {
+    if !authority.is_signer {
+        msg!("Error: Authority is not signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    
+    if !config.is_relayer(authority.key) {
+        if config.find_relayer(authority.key).is_some() {
+            msg!("Error: Relayer {} is disabled", authority.key);
+        } else {
+            msg!("Error: Authority {} is not an authorized relayer", authority.key);
+        }
+        return Err(SettlementError::InvalidAuthority.into());
+    }
+    
+    Ok(())
+}
+
+/// 验证program未被全局暂停（由process_instruction按pause_gated_config_index调用）
+fn check_not_paused(config: &ProgramConfig) -> ProgramResult {
+    if config.paused {
+        msg!("Error: Program is paused");
+        return Err(SettlementError::ProgramPaused.into());
+    }
+    
+    Ok(())
+}
+
+/// 验证batch涉及的市场均未下架且未被单独暂停
+fn check_markets_open(config: &ProgramConfig, markets: &[Market]) -> ProgramResult {
+    for market in markets {
+        if market.status == MarketStatus::Delisted {
+            msg!("Error: Market {} is delisted", market.symbol);
+            return Err(SettlementError::MarketDelisted.into());
+        }
+        
+        if config.is_market_paused(market.market_id) {
+            msg!("Error: Market {} is paused", market.symbol);
+            return Err(SettlementError::MarketPaused.into());
+        }
+    }
+    
+    Ok(())
+}
+
+/// 读取并校验Market账户（symbol需与trade.market一致）
+fn load_market(
+    program_id: &Pubkey,
+    market_account: &AccountInfo,
+    symbol: &str,
+) -> Result<Market, ProgramError> {
+    // 未登记的市场PDA不存在（不属于本program）
+    if market_account.owner != program_id {
+        msg!("Error: Market {} is not registered", symbol);
+        return Err(SettlementError::UnknownMarket.into());
+    }
+    
+    // Market按最大容量分配，尾部可能是未使用的零字节
+    let market = Market::deserialize(&mut &market_account.data.borrow()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    if market.discriminator != Market::DISCRIMINATOR || market.symbol != symbol {
+        msg!("Error: Account {} is not the Market for {}", market_account.key, symbol);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    let expected_pda = Pubkey::create_program_address(
+        &[b"market", symbol.as_bytes(), &[market.bump]],
+        program_id,
+    )
+    .map_err(|_| SettlementError::InvalidSettlementAccount)?;
+    
+    if market_account.key != &expected_pda {
+        msg!("Error: Market PDA mismatch for {}", symbol);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    Ok(market)
+}
+
+/// 验证authority是已签名的admin
+fn check_admin(config: &ProgramConfig, authority: &AccountInfo) -> ProgramResult {
+    if !authority.is_signer {
+        msg!("Error: Admin is not signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    
+    if authority.key != &config.admin {
+        msg!("Error: {} is not the config admin", authority.key);
+        return Err(SettlementError::UnauthorizedAdmin.into());
+    }
+    
+    Ok(())
+}
+
+/// 通过System Program CPI创建PDA账户（由payer支付租金）
+///
+/// 任何人都可以提前向PDA地址转入lamports，此时create_account会失败。
+/// 对已有余额但仍归System Program所有的账户，补足租金后用allocate + assign完成创建。
+fn create_pda_account<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    new_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    space: usize,
+    signer_seeds: &[&[u8]],
+) -> ProgramResult {
+    let rent = Rent::get()?;
+    let required_lamports = rent.minimum_balance(space);
+    
+    msg!("  Space: {} bytes", space);
+    msg!("  Rent: {} lamports", required_lamports);
+    
+    let current_lamports = new_account.lamports();
+    if current_lamports == 0 {
+        let create_account_ix = system_instruction::create_account(
+            payer.key,
+            new_account.key,
+            required_lamports,
+            space as u64,
+            program_id,
+        );
+        
+        return invoke_signed(
+            &create_account_ix,
+            &[
+                payer.clone(),
+                new_account.clone(),
+                system_program.clone(),
+            ],
+            &[signer_seeds],
+        );
+    }
+    
+    if new_account.owner != &solana_program::system_program::id() {
+        msg!("Error: {} is already owned by {}", new_account.key, new_account.owner);
+        return Err(SettlementError::AccountAlreadyExists.into());
+    }
+    
+    msg!("  Pre-funded: {} lamports", current_lamports);
+    
+    let shortfall = required_lamports.saturating_sub(current_lamports);
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, new_account.key, shortfall),
+            &[payer.clone(), new_account.clone(), system_program.clone()],
+        )?;
+    }
+    
+    invoke_signed(
+        &system_instruction::allocate(new_account.key, space as u64),
+        &[new_account.clone(), system_program.clone()],
+        &[signer_seeds],
+    )?;
+    
+    invoke_signed(
+        &system_instruction::assign(new_account.key, program_id),
+        &[new_account.clone(), system_program.clone()],
+        &[signer_seeds],
+    )
+}
+
+/// 读取program的upgrade authority（None = program不可升级）
+fn load_upgrade_authority(
+    program_id: &Pubkey,
+    program_data_account: &AccountInfo,
+) -> Result<Option<Pubkey>, ProgramError> {
+    let (expected_program_data, _) = Pubkey::find_program_address(
+        &[program_id.as_ref()],
+        &bpf_loader_upgradeable::id(),
+    );
+    
+    if program_data_account.key != &expected_program_data
+        || program_data_account.owner != &bpf_loader_upgradeable::id()
+    {
+        msg!("Error: ProgramData account mismatch. Expected {}, got {}",
+            expected_program_data, program_data_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    // ProgramData的元数据为bincode编码的UpgradeableLoaderState，其后是program代码
+    let metadata_len = UpgradeableLoaderState::size_of_programdata_metadata();
+    let data = program_data_account.data.borrow();
+    let metadata = data.get(..metadata_len).ok_or(SettlementError::InvalidSettlementAccount)?;
+    
+    match limited_deserialize(metadata, metadata_len as u64) {
+        Ok(UpgradeableLoaderState::ProgramData { upgrade_authority_address, .. }) => Ok(upgrade_authority_address),
+        _ => {
+            msg!("Error: {} is not a ProgramData account", program_data_account.key);
+            Err(SettlementError::InvalidSettlementAccount.into())
+        }
+    }
+}
+
+/// account是否已由本程序创建（owner为本程序且discriminator匹配）
+///
+/// 不能只看lamports：任何人都可以向尚未创建的PDA转账。
+fn is_initialized_account(program_id: &Pubkey, account: &AccountInfo, discriminator: u64) -> bool {
+    if account.owner != program_id {
+        return false;
+    }
+    
+    let data = account.data.borrow();
+    data.len() >= 8 && data[..8] == discriminator.to_le_bytes()
+}
+
+/// 校验relayer列表：不超过上限且无重复
+fn validate_relayers(relayers: &[Pubkey]) -> ProgramResult {
+    if relayers.len() > ProgramConfig::MAX_RELAYERS {
+        msg!("Error: {} relayers exceeds max {}", relayers.len(), ProgramConfig::MAX_RELAYERS);
+        return Err(SettlementError::TooManyRelayers.into());
+    }
+    
+    for (i, relayer) in relayers.iter().enumerate() {
+        if relayers[..i].contains(relayer) {
+            msg!("Error: Duplicate relayer {}", relayer);
+            return Err(ProgramError::InvalidArgument);
         }
     }
+    
+    Ok(())
 }
 
 /// 初始化用户的Settlement账户（首次交易时调用）
@@ -53,24 +529,16 @@ fn process_initialize_user(
     let user_settlement_account = next_account_info(account_iter)?;
     let authority = next_account_info(account_iter)?;
     let system_program = next_account_info(account_iter)?;
+    let config_account = next_account_info(account_iter)?;
+    let global_stats_account = next_account_info(account_iter)?;
     
     msg!("Initializing UserSettlement for: {}", wallet);
     
-    // 验证authority签名
-    if !authority.is_signer {
-        msg!("Error: Authority is not signer");
-        return Err(ProgramError::MissingRequiredSignature);
-    }
-    
     // 验证authority是授权的relayer
-    let authorized_key = AUTHORIZED_RELAYER.parse::<Pubkey>()
-        .map_err(|_| SettlementError::InvalidAuthority)?;
+    let config = load_config(program_id, config_account)?;
+    check_relayer(&config, authority)?;
     
-    if authority.key != &authorized_key {
-        msg!("Error: Authority {} is not authorized relayer {}", 
-            authority.key, authorized_key);
-        return Err(SettlementError::InvalidAuthority.into());
-    }
+    let mut global_stats = load_global_stats(program_id, global_stats_account)?;
     
     // 派生UserSettlement PDA
     let (expected_pda, bump) = Pubkey::find_program_address(
@@ -85,38 +553,20 @@ fn process_initialize_user(
     }
     
     // 验证account尚不存在
-    if user_settlement_account.lamports() > 0 {
+    if is_initialized_account(program_id, user_settlement_account, UserSettlement::DISCRIMINATOR) {
         msg!("Error: UserSettlement already exists");
         return Err(SettlementError::AccountAlreadyExists.into());
     }
     
-    // 计算租金
-    let rent = Rent::get()?;
-    let space = UserSettlement::SIZE;
-    let required_lamports = rent.minimum_balance(space);
-    
-    msg!("Creating UserSettlement PDA...");
-    msg!("  Space: {} bytes", space);
-    msg!("  Rent: {} lamports", required_lamports);
-    
     // 创建account（通过CPI调用System Program）
-    use solana_program::system_instruction;
-    let create_account_ix = system_instruction::create_account(
-        authority.key,
-        user_settlement_account.key,
-        required_lamports,
-        space as u64,
+    msg!("Creating UserSettlement PDA...");
+    create_pda_account(
         program_id,
-    );
-    
-    invoke_signed(
-        &create_account_ix,
-        &[
-            authority.clone(),
-            user_settlement_account.clone(),
-            system_program.clone(),
-        ],
-        &[&[b"user_settlement", wallet.as_ref(), &[bump]]],
+        authority,
+        user_settlement_account,
+        system_program,
+        UserSettlement::SIZE,
+        &[b"user_settlement", wallet.as_ref(), &[bump]],
     )?;
     
     // 初始化数据（空统计）
@@ -129,7 +579,11 @@ fn process_initialize_user(
     
     user_settlement_account.data.borrow_mut().copy_from_slice(&serialized);
     
-    msg!("✅ UserSettlement initialized for {}", wallet);
+    global_stats.total_users = global_stats.total_users.checked_add(1)
+        .ok_or(SettlementError::ArithmeticOverflow)?;
+    save_global_stats(&global_stats, global_stats_account)?;
+    
+    msg!("✅ UserSettlement initialized for {} (users: {})", wallet, global_stats.total_users);
     
     Ok(())
 }
@@ -138,119 +592,280 @@ fn process_initialize_user(
 fn process_record_settlement(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    batch_id: String,
+    batch_id: BatchId,
     trades: Vec<CompleteTrade>,
+    total_volume_e6: UsdcE6,
+    total_fees_e6: UsdcE6,
+    data_hash: [u8; 32],
 ) -> ProgramResult {
+    // 验证batch_id格式并统一为16字节UUID
+    let batch_bytes = parse_batch_id(&batch_id)?;
+    let batch_id = format_batch_id(&batch_bytes);
+    
     msg!("Recording settlement...");
     msg!("  Batch ID: {}", batch_id);
     msg!("  Trades: {}", trades.len());
     
-    // 最后一个account是authority
-    let accounts_list: Vec<_> = accounts.iter().collect();
-    let authority = accounts_list.last()
-        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let account_iter = &mut accounts.iter();
+    
+    let authority = next_account_info(account_iter)?;
+    let config_account = next_account_info(account_iter)?;
+    let batch_account = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    let global_stats_account = next_account_info(account_iter)?;
+    let fee_schedule_account = next_account_info(account_iter)?;
     
     // 验证authority
-    if !authority.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+    let mut config = load_config(program_id, config_account)?;
+    check_relayer(&config, authority)?;
     
-    let authorized_key = AUTHORIZED_RELAYER.parse::<Pubkey>()
-        .map_err(|_| SettlementError::InvalidAuthority)?;
+    let mut global_stats = load_global_stats(program_id, global_stats_account)?;
+    let fee_schedule = load_fee_schedule(program_id, fee_schedule_account)?;
     
-    if authority.key != &authorized_key {
-        return Err(SettlementError::InvalidAuthority.into());
+    // 剩余accounts：每个唯一wallet一个UserSettlement，之后每个市场一个Market，
+    // 再之后每个(用户, 市场)组合一个UserMarketStats，每个市场一个MarketStats（均按首次出现顺序，
+    // config.stats_tracking跳过的统计不传），最后是taker的推荐人的ReferrerStats
+    // （推荐人保存在UserSettlement中，验证用户后再确定）
+    let (wallets, trade_user_indices) = index_trade_wallets(&trades);
+    let (symbols, trade_market_indices) = index_trade_markets(&trades);
+    let (user_markets, trade_user_market_indices) =
+        index_trade_user_markets(&trade_user_indices, &trade_market_indices);
+    let StatsTracking { skip_market_stats, skip_user_market_stats } = config.stats_tracking;
+    let user_market_count = if skip_user_market_stats { 0 } else { user_markets.len() };
+    let market_stats_count = if skip_market_stats { 0 } else { symbols.len() };
+    let remaining_accounts = account_iter.as_slice();
+    
+    if remaining_accounts.len() < wallets.len() + symbols.len() + user_market_count + market_stats_count {
+        msg!("Error: Expected {} UserSettlement, {} Market, {} UserMarketStats and {} MarketStats accounts, got {}",
+            wallets.len(), symbols.len(), user_market_count, market_stats_count, remaining_accounts.len());
+        return Err(ProgramError::NotEnoughAccountKeys);
     }
+    let (user_accounts, remaining_accounts) = remaining_accounts.split_at(wallets.len());
+    let (market_accounts, remaining_accounts) = remaining_accounts.split_at(symbols.len());
+    let (stats_accounts, remaining_accounts) = remaining_accounts.split_at(user_market_count);
+    let (market_stats_accounts, referrer_accounts) = remaining_accounts.split_at(market_stats_count);
+    
+    let markets = symbols.iter()
+        .zip(market_accounts)
+        .map(|(symbol, market_account)| load_market(program_id, market_account, symbol))
+        .collect::<Result<Vec<_>, _>>()?;
+    check_markets_open(&config, &markets)?;
     
     // 验证trades数据
-    validate_settlement_data(&trades)?;
+    validate_settlement_data(&trades, &markets)?;
+    
+    // 验证批次汇总和数据hash
+    verify_batch_commitment(&trades, total_volume_e6, total_fees_e6, &data_hash)?;
+    
+    let clock = solana_program::clock::Clock::get()?;
+    let now = clock.unix_timestamp * 1000;
+    
+    // 创建SettlementBatch PDA（已存在说明是重复提交）
+    let (expected_batch_pda, batch_bump) = Pubkey::find_program_address(
+        &[b"settlement", &batch_bytes],
+        program_id,
+    );
+    
+    if batch_account.key != &expected_batch_pda {
+        msg!("Error: SettlementBatch PDA mismatch. Expected {}, got {}",
+            expected_batch_pda, batch_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    if is_initialized_account(program_id, batch_account, SettlementBatch::DISCRIMINATOR) {
+        msg!("Error: Batch {} already recorded", batch_id);
+        return Err(SettlementError::AccountAlreadyExists.into());
+    }
+    
+    // 验证engine_seq接续上一个batch（重复batch已在上面以AccountAlreadyExists拒绝）
+    let last_engine_seq = match validate_engine_sequence(
+        &trades,
+        config.last_engine_seq,
+        config.allow_seq_gaps,
+    ) {
+        Ok(seq) => seq,
+        Err(e) => {
+            msg!("Error: {}, last settled seq {}", e, config.last_engine_seq);
+            return Err(e.into());
+        }
+    };
+    
+    let merkle_root = trades_merkle_root(&trades)?;
+    
+    msg!("Creating SettlementBatch PDA...");
+    create_pda_account(
+        program_id,
+        authority,
+        batch_account,
+        system_program,
+        SettlementBatch::SIZE,
+        &[b"settlement", &batch_bytes, &[batch_bump]],
+    )?;
+    
+    let batch = SettlementBatch {
+        discriminator: SettlementBatch::DISCRIMINATOR,
+        version: SettlementBatch::VERSION,
+        bump: batch_bump,
+        reserved: [0; 6],
+        batch_id: batch_bytes,
+        batch_hash: data_hash,
+        merkle_root,
+        trade_count: trades.len() as u32,
+        total_volume_e6,
+        total_fees_e6,
+        relayer: *authority.key,
+        slot: clock.slot,
+        recorded_at: now,
+    };
+    batch.serialize(&mut &mut batch_account.data.borrow_mut()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    // 记录relayer最后使用时间和最新engine_seq
+    if let Some(relayer) = config.find_relayer_mut(authority.key) {
+        relayer.last_used_at = now;
+    }
+    config.last_engine_seq = last_engine_seq;
+    save_config(&config, config_account)?;
+    
+    global_stats.record_batch(batch_bytes, &trades, now)?;
+    save_global_stats(&global_stats, global_stats_account)?;
     
     // Emit settlement开始日志
-    msg!("SETTLEMENT_START|batch_id:{}|trades:{}|timestamp:{}", 
-        batch_id, trades.len(), 
-        solana_program::clock::Clock::get()?.unix_timestamp * 1000);
-    
-    // 收集需要更新的用户PDAs
-    // 格式：(wallet, pda, is_taker, trade_index)
-    let mut user_updates: Vec<(Pubkey, Pubkey, bool, usize)> = Vec::new();
-    
-    for (idx, trade) in trades.iter().enumerate() {
-        // Taker PDA
-        let (taker_pda, _) = Pubkey::find_program_address(
-            &[b"user_settlement", trade.taker_wallet.as_ref()],
-            program_id,
-        );
-        user_updates.push((trade.taker_wallet, taker_pda, true, idx));
-        
-        // Maker PDA  
-        let (maker_pda, _) = Pubkey::find_program_address(
-            &[b"user_settlement", trade.maker_wallet.as_ref()],
+    msg!("SETTLEMENT_START|batch_id:{}|trades:{}|timestamp:{}|relayer:{}", 
+        batch_id, trades.len(), now, authority.key);
+    
+    // 每个唯一wallet对应一个UserSettlement account，trades通过index引用
+    // 验证每个UserSettlement account
+    // 使用account中保存的bump + create_program_address验证PDA，
+    // 避免find_program_address的bump搜索（每次至少1500 CU）
+    for (wallet, user_account) in wallets.iter().zip(user_accounts) {
+        check_user_settlement(program_id, authority, user_account, system_program, wallet)?;
+    }
+    
+    // 验证batch中taker的推荐人的ReferrerStats account
+    let user_referrers = user_accounts.iter()
+        .map(|user_account| Ok(UserSettlement::load(&user_account.data.borrow())?.referrer()))
+        .collect::<Result<Vec<_>, SettlementError>>()?;
+    let (referrers, user_referrer_indices) = index_trade_referrers(&user_referrers, &trade_user_indices);
+    
+    if referrer_accounts.len() < referrers.len() {
+        msg!("Error: Expected {} ReferrerStats accounts, got {}", referrers.len(), referrer_accounts.len());
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let referrer_accounts = &referrer_accounts[..referrers.len()];
+    
+    for (referrer, referrer_account) in referrers.iter().zip(referrer_accounts) {
+        check_referrer_stats(program_id, referrer_account, referrer)?;
+    }
+    
+    // 验证每个UserMarketStats account，首次在该市场交易的用户创建新account
+    // （跳过UserMarketStats时stats_accounts为空）
+    for (&(user_idx, market_idx), stats_account) in user_markets.iter().zip(stats_accounts) {
+        load_or_create_user_market_stats(
             program_id,
-        );
-        user_updates.push((trade.maker_wallet, maker_pda, false, idx));
+            authority,
+            stats_account,
+            system_program,
+            &wallets[user_idx],
+            markets[market_idx].market_id,
+        )?;
     }
     
-    // 更新每个UserSettlement account
-    let mut account_idx = 0;
-    for (wallet, expected_pda, is_taker, trade_idx) in user_updates {
-        if account_idx >= accounts.len() - 1 {
-            msg!("Error: Not enough accounts provided");
-            return Err(ProgramError::NotEnoughAccountKeys);
-        }
-        
-        let user_account = &accounts[account_idx];
-        account_idx += 1;
+    // 跳过MarketStats时market_stats_accounts为空，下面不更新
+    let mut market_stats = markets.iter()
+        .zip(market_stats_accounts)
+        .map(|(market, stats_account)| load_market_stats(program_id, stats_account, market))
+        .collect::<Result<Vec<_>, _>>()?;
+    
+    // 按trades顺序校验手续费并更新统计和持仓（直接写入account数据）
+    // 费率档位按用户在该trade之前的30天成交量确定（未设置FeeSchedule时不校验）
+    for (i, trade) in trades.iter().enumerate() {
+        let (taker_idx, maker_idx) = trade_user_indices[i];
+        let (taker_stats_idx, maker_stats_idx) = trade_user_market_indices[i];
         
-        // 验证PDA正确
-        if user_account.key != &expected_pda {
-            msg!("Error: UserSettlement PDA mismatch for wallet {}", wallet);
-            msg!("  Expected: {}, Got: {}", expected_pda, user_account.key);
-            return Err(SettlementError::InvalidSettlementAccount.into());
+        {
+            let mut data = user_accounts[taker_idx].data.borrow_mut();
+            let taker = UserSettlement::load_mut(&mut data)?;
+            let volume_30d = taker.trailing_volume(trade.ts_ms, FeeSchedule::VOLUME_WINDOW_DAYS)?;
+            if let Some(Err(e)) = fee_schedule.as_ref().map(|f| f.validate_taker_fee(trade, volume_30d)) {
+                msg!("Error: Taker fee mismatch in trade {} (rate {}, 30d volume {})",
+                    trade.id, trade.fee_rate_taker_e5, volume_30d.0);
+                return Err(e.into());
+            }
+            taker.update_as_taker(trade)?;
         }
-        
-        // 验证account owner
-        if user_account.owner != program_id {
-            msg!("Error: Account owner mismatch");
-            return Err(ProgramError::IllegalOwner);
+        if let Some(referrer_idx) = user_referrer_indices[taker_idx] {
+            ReferrerStats::load_mut(&mut referrer_accounts[referrer_idx].data.borrow_mut())?
+                .record_referred_trade(trade, config.referral_share_bp)?;
+        }
+        {
+            let mut data = user_accounts[maker_idx].data.borrow_mut();
+            let maker = UserSettlement::load_mut(&mut data)?;
+            let volume_30d = maker.trailing_volume(trade.ts_ms, FeeSchedule::VOLUME_WINDOW_DAYS)?;
+            if let Some(Err(e)) = fee_schedule.as_ref().map(|f| f.validate_maker_fee(trade, volume_30d)) {
+                msg!("Error: Maker fee mismatch in trade {} (rate {}, 30d volume {})",
+                    trade.id, trade.fee_rate_maker_e5, volume_30d.0);
+                return Err(e.into());
+            }
+            maker.update_as_maker(trade)?;
         }
         
-        // 读取、更新、写回UserSettlement数据
-        let mut user_settlement = UserSettlement::try_from_slice(&user_account.data.borrow())
-            .map_err(|_| SettlementError::SerializationError)?;
-        
-        let trade = &trades[trade_idx];
-        
-        if is_taker {
-            user_settlement.update_as_taker(trade);
-            msg!("  Updated taker: {} (trades: {})", wallet, user_settlement.total_trades);
-        } else {
-            user_settlement.update_as_maker(trade);
-            msg!("  Updated maker: {} (trades: {})", wallet, user_settlement.total_trades);
+        // 持仓和已实现盈亏（maker方向与taker相反），跳过UserMarketStats时不累计
+        if !skip_user_market_stats {
+            let params = &markets[trade_market_indices[i]].params;
+            let taker_pnl = {
+                let mut data = stats_accounts[taker_stats_idx].data.borrow_mut();
+                let stats = UserMarketStats::load_mut(&mut data)?;
+                stats.update_as_taker(trade)?;
+                stats.apply_fill(trade.taker_side, trade.price_e6, trade.qty_e6, params)?
+            };
+            let maker_pnl = {
+                let mut data = stats_accounts[maker_stats_idx].data.borrow_mut();
+                let stats = UserMarketStats::load_mut(&mut data)?;
+                stats.update_as_maker(trade)?;
+                stats.apply_fill(trade.taker_side.opposite(), trade.price_e6, trade.qty_e6, params)?
+            };
+            for (user_idx, pnl) in [(taker_idx, taker_pnl), (maker_idx, maker_pnl)] {
+                let mut data = user_accounts[user_idx].data.borrow_mut();
+                let user = UserSettlement::load_mut(&mut data)?;
+                user.realized_pnl_e6 = user.realized_pnl_e6.checked_add(pnl)?;
+            }
         }
         
-        // 写回数据
-        let serialized = user_settlement.try_to_vec()
+        if let Some(stats) = market_stats.get_mut(trade_market_indices[i]) {
+            stats.update(trade)?;
+        }
+    }
+    
+    for (stats, stats_account) in market_stats.iter().zip(market_stats_accounts) {
+        stats.serialize(&mut &mut stats_account.data.borrow_mut()[..])
             .map_err(|_| SettlementError::SerializationError)?;
-        
-        user_account.data.borrow_mut().copy_from_slice(&serialized);
+        msg!("  Market {}: trades {}, last price {}, seq {}",
+            stats.market_id, stats.total_trades, stats.last_price_e6.0, stats.last_engine_seq);
     }
     
-    // Emit详细的trade logs（所有19字段）
-    let mut total_volume_e6: i64 = 0;
-    let mut total_fees_e6: i64 = 0;
+    for user_account in user_accounts {
+        let data = user_account.data.borrow();
+        let user_settlement = UserSettlement::load(&data)?;
+        msg!("  Updated user: {} (trades: {})", user_settlement.wallet, user_settlement.total_trades);
+    }
+    
+    for referrer_account in referrer_accounts {
+        let data = referrer_account.data.borrow();
+        let stats = ReferrerStats::load(&data)?;
+        msg!("  Referrer {}: referred fees {}, rewards {}",
+            stats.referrer, stats.referred_fees_e6.0, stats.rewards_e6.0);
+    }
     
+    // Emit详细的trade logs（所有19字段）
     for trade in &trades {
-        let notional = (trade.price_e6 as i128 * trade.qty_e6 as i128 / 1_000_000) as i64;
-        total_volume_e6 += notional;
-        total_fees_e6 += trade.taker_fee_e6 + trade.maker_fee_e6;
-        
         // Trade基础信息
         msg!("TRADE|id:{}|market:{}|price_e6:{}|qty_e6:{}|notional_e6:{}|side:{}|ts:{}|seq:{}", 
             trade.id,
             trade.market,
-            trade.price_e6,
-            trade.qty_e6,
-            trade.notional_e6,
+            trade.price_e6.0,
+            trade.qty_e6.0,
+            trade.notional_e6.0,
             if matches!(trade.taker_side, crate::state::Side::Buy) { "buy" } else { "sell" },
             trade.ts_ms,
             trade.engine_seq
@@ -277,21 +892,1056 @@ fn process_record_settlement(
         );
         
         // 手续费信息
-        msg!("  fees|taker:{}|maker:{}|taker_rate:{}bp|maker_rate:{}bp", 
-            trade.taker_fee_e6,
-            trade.maker_fee_e6,
-            trade.fee_rate_taker_bp,
-            trade.fee_rate_maker_bp
+        msg!("  fees|taker:{}|maker:{}|taker_rate_e5:{}|maker_rate_e5:{}", 
+            trade.taker_fee_e6.0,
+            trade.maker_fee_e6.0,
+            trade.fee_rate_taker_e5,
+            trade.fee_rate_maker_e5
         );
     }
     
     // Emit settlement结束日志
-    msg!("SETTLEMENT_END|batch_id:{}|total_volume:{}|total_fees:{}", 
-        batch_id, total_volume_e6, total_fees_e6);
+    msg!("SETTLEMENT_END|batch_id:{}|total_volume:{}|total_fees:{}|relayer:{}|merkle_root:{}", 
+        batch_id, total_volume_e6.0, total_fees_e6.0, authority.key,
+        solana_program::hash::Hash::new_from_array(merkle_root));
     
     msg!("✅ Settlement recorded successfully!");
     msg!("  {} trades processed", trades.len());
-    msg!("  {} user accounts updated", account_idx);
+    msg!("  {} user accounts updated", user_accounts.len());
+    msg!("  {} user market stats updated", user_market_count);
+    
+    Ok(())
+}
+
+/// 读取并验证市场的MarketStats account
+fn load_market_stats(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+    market: &Market,
+) -> Result<MarketStats, ProgramError> {
+    if account.owner != program_id {
+        msg!("Error: MarketStats for {} not owned by program", market.symbol);
+        return Err(ProgramError::IllegalOwner);
+    }
+    
+    let stats = MarketStats::deserialize(&mut &account.data.borrow()[..])
+        .map_err(|_| SettlementError::InvalidSettlementAccount)?;
+    
+    if stats.discriminator != MarketStats::DISCRIMINATOR || stats.market_id != market.market_id {
+        msg!("Error: Invalid MarketStats account for {}", market.symbol);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    let expected_pda = Pubkey::create_program_address(
+        &[b"market_stats", market.symbol.as_bytes(), &[stats.bump]],
+        program_id,
+    )
+    .map_err(|_| SettlementError::InvalidSettlementAccount)?;
+    
+    if account.key != &expected_pda {
+        msg!("Error: MarketStats PDA mismatch for {}", market.symbol);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    Ok(stats)
+}
+
+/// 验证wallet的UserSettlement account，旧版本account先扩容到当前版本（payer补足租金）
+///
+/// 迁移后的校验失败会回滚整笔交易。
+fn check_user_settlement<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    user_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    wallet: &Pubkey,
+) -> ProgramResult {
+    // 验证account owner（之后才能信任其中保存的bump）
+    if user_account.owner != program_id {
+        msg!("Error: UserSettlement for {} not owned by program", wallet);
+        return Err(ProgramError::IllegalOwner);
+    }
+    
+    if user_account.data_len() < UserSettlement::SIZE {
+        migrate_user_settlement(payer, user_account, system_program)?;
+    }
+    
+    let data = user_account.data.borrow();
+    let user_settlement = UserSettlement::load(&data)?;
+    
+    if user_settlement.discriminator != UserSettlement::DISCRIMINATOR {
+        msg!("Error: UserSettlement discriminator mismatch for {}", user_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    // 验证PDA正确
+    let expected_pda = Pubkey::create_program_address(
+        &[b"user_settlement", wallet.as_ref(), &[user_settlement.bump]],
+        program_id,
+    )
+    .map_err(|_| SettlementError::InvalidSettlementAccount)?;
+    
+    if user_account.key != &expected_pda {
+        msg!("Error: UserSettlement PDA mismatch for wallet {}", wallet);
+        msg!("  Expected: {}, Got: {}", expected_pda, user_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    Ok(())
+}
+
+/// 验证推荐人的ReferrerStats account（使用保存的bump验证PDA）
+fn check_referrer_stats(
+    program_id: &Pubkey,
+    account: &AccountInfo,
+    referrer: &Pubkey,
+) -> ProgramResult {
+    if account.owner != program_id {
+        msg!("Error: ReferrerStats for {} not owned by program", referrer);
+        return Err(ProgramError::IllegalOwner);
+    }
+    
+    let data = account.data.borrow();
+    let stats = ReferrerStats::load(&data)?;
+    
+    if stats.discriminator != ReferrerStats::DISCRIMINATOR || stats.referrer != *referrer {
+        msg!("Error: Invalid ReferrerStats account for {}", referrer);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    let expected_pda = Pubkey::create_program_address(
+        &[b"referrer_stats", referrer.as_ref(), &[stats.bump]],
+        program_id,
+    )
+    .map_err(|_| SettlementError::InvalidSettlementAccount)?;
+    
+    if account.key != &expected_pda {
+        msg!("Error: ReferrerStats PDA mismatch for {}", referrer);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    Ok(())
+}
+
+/// 将旧版本UserSettlement扩容并按当前布局重写，新增字段为0（payer补足租金）
+fn migrate_user_settlement<'a>(
+    payer: &AccountInfo<'a>,
+    user_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    // 按旧布局读出，扩容后以当前布局重写（v3的累计字段位置与v1/v2不同）
+    let migrated = LegacyUserSettlement::read(&user_account.data.borrow())?.migrate();
+    
+    let rent = Rent::get()?;
+    let required_lamports = rent.minimum_balance(UserSettlement::SIZE)
+        .saturating_sub(user_account.lamports());
+    
+    msg!("Migrating UserSettlement {} to v{} (+{} lamports)",
+        user_account.key, UserSettlement::VERSION, required_lamports);
+    
+    if required_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, user_account.key, required_lamports),
+            &[payer.clone(), user_account.clone(), system_program.clone()],
+        )?;
+    }
+    
+    user_account.realloc(UserSettlement::SIZE, false)?;
+    *UserSettlement::load_mut(&mut user_account.data.borrow_mut())? = migrated;
+    
+    Ok(())
+}
+
+/// 验证UserMarketStats PDA；account不存在时创建（payer支付租金）
+fn load_or_create_user_market_stats<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    stats_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    wallet: &Pubkey,
+    market_id: u16,
+) -> ProgramResult {
+    let market_id_bytes = market_id.to_le_bytes();
+    
+    if stats_account.owner == program_id {
+        let data = stats_account.data.borrow();
+        let stats = UserMarketStats::load(&data)?;
+        
+        if stats.discriminator != UserMarketStats::DISCRIMINATOR {
+            msg!("Error: UserMarketStats discriminator mismatch for {}", stats_account.key);
+            return Err(SettlementError::InvalidSettlementAccount.into());
+        }
+        
+        // 使用保存的bump验证PDA
+        let expected_pda = Pubkey::create_program_address(
+            &[b"user_market", wallet.as_ref(), &market_id_bytes, &[stats.bump]],
+            program_id,
+        )
+        .map_err(|_| SettlementError::InvalidSettlementAccount)?;
+        
+        if stats_account.key != &expected_pda {
+            msg!("Error: UserMarketStats PDA mismatch for wallet {} in market {}", wallet, market_id);
+            return Err(SettlementError::InvalidSettlementAccount.into());
+        }
+        
+        return Ok(());
+    }
+    
+    let (expected_pda, bump) = Pubkey::find_program_address(
+        &[b"user_market", wallet.as_ref(), &market_id_bytes],
+        program_id,
+    );
+    
+    if stats_account.key != &expected_pda {
+        msg!("Error: UserMarketStats PDA mismatch for wallet {} in market {}", wallet, market_id);
+        msg!("  Expected: {}, Got: {}", expected_pda, stats_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    msg!("Creating UserMarketStats PDA for {} in market {}...", wallet, market_id);
+    create_pda_account(
+        program_id,
+        payer,
+        stats_account,
+        system_program,
+        UserMarketStats::SIZE,
+        &[b"user_market", wallet.as_ref(), &market_id_bytes, &[bump]],
+    )?;
+    
+    *UserMarketStats::load_mut(&mut stats_account.data.borrow_mut())? =
+        UserMarketStats::new(*wallet, market_id, bump);
+    
+    Ok(())
+}
+
+/// 初始化全局配置（签名者成为admin）
+fn process_initialize_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    relayers: Vec<Pubkey>,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    let global_stats_account = next_account_info(account_iter)?;
+    let program_data_account = next_account_info(account_iter)?;
+    
+    msg!("Initializing ProgramConfig, admin: {}", admin.key);
+    
+    if !admin.is_signer {
+        msg!("Error: Admin is not signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    
+    // 只有upgrade authority可以初始化，防止部署后被抢先初始化
+    match load_upgrade_authority(program_id, program_data_account)? {
+        Some(authority) if &authority == admin.key => {}
+        Some(authority) => {
+            msg!("Error: {} is not the upgrade authority {}", admin.key, authority);
+            return Err(SettlementError::UnauthorizedAdmin.into());
+        }
+        None => {
+            msg!("Error: Program has no upgrade authority");
+            return Err(SettlementError::UnauthorizedAdmin.into());
+        }
+    }
+    
+    validate_relayers(&relayers)?;
+    
+    let (expected_pda, bump) = Pubkey::find_program_address(&[b"config"], program_id);
+    
+    if config_account.key != &expected_pda {
+        msg!("Error: Config PDA mismatch. Expected {}, got {}",
+            expected_pda, config_account.key);
+        return Err(SettlementError::InvalidConfigAccount.into());
+    }
+    
+    if is_initialized_account(program_id, config_account, ProgramConfig::DISCRIMINATOR) {
+        msg!("Error: ProgramConfig already exists");
+        return Err(SettlementError::AccountAlreadyExists.into());
+    }
+    
+    msg!("Creating ProgramConfig PDA...");
+    create_pda_account(
+        program_id,
+        admin,
+        config_account,
+        system_program,
+        ProgramConfig::SIZE,
+        &[b"config", &[bump]],
+    )?;
+    
+    let now = solana_program::clock::Clock::get()?.unix_timestamp * 1000;
+    let relayers = relayers.into_iter()
+        .map(|pubkey| RelayerInfo::new(pubkey, String::new(), now))
+        .collect();
+    
+    let config = ProgramConfig::new(*admin.key, bump, relayers);
+    save_config(&config, config_account)?;
+    
+    let (expected_stats_pda, stats_bump) = Pubkey::find_program_address(&[b"global_stats"], program_id);
+    
+    if global_stats_account.key != &expected_stats_pda {
+        msg!("Error: GlobalStats PDA mismatch. Expected {}, got {}",
+            expected_stats_pda, global_stats_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    msg!("Creating GlobalStats PDA...");
+    create_pda_account(
+        program_id,
+        admin,
+        global_stats_account,
+        system_program,
+        GlobalStats::SIZE,
+        &[b"global_stats", &[stats_bump]],
+    )?;
+    save_global_stats(&GlobalStats::new(stats_bump), global_stats_account)?;
+    
+    for relayer in &config.relayers {
+        msg!("  Authorized relayer: {}", relayer.pubkey);
+    }
+    msg!("✅ ProgramConfig initialized");
+    
+    Ok(())
+}
+
+/// 登记新的relayer
+fn process_add_relayer(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    relayer: Pubkey,
+    label: String,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    if label.len() > RelayerInfo::MAX_LABEL_LEN {
+        msg!("Error: Label exceeds {} bytes", RelayerInfo::MAX_LABEL_LEN);
+        return Err(ProgramError::InvalidArgument);
+    }
+    
+    if config.find_relayer(&relayer).is_some() {
+        msg!("Error: Relayer {} already registered", relayer);
+        return Err(SettlementError::RelayerAlreadyExists.into());
+    }
+    
+    if config.relayers.len() >= ProgramConfig::MAX_RELAYERS {
+        msg!("Error: Relayer registry is full ({})", ProgramConfig::MAX_RELAYERS);
+        return Err(SettlementError::TooManyRelayers.into());
+    }
+    
+    let now = solana_program::clock::Clock::get()?.unix_timestamp * 1000;
+    config.relayers.push(RelayerInfo::new(relayer, label, now));
+    save_config(&config, config_account)?;
+    
+    msg!("✅ Relayer added: {} ({} total)", relayer, config.relayers.len());
+    
+    Ok(())
+}
+
+/// 移除relayer
+fn process_remove_relayer(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    relayer: Pubkey,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    let position = config.relayers.iter()
+        .position(|r| r.pubkey == relayer)
+        .ok_or(SettlementError::RelayerNotFound)?;
+    
+    config.relayers.remove(position);
+    save_config(&config, config_account)?;
+    
+    msg!("✅ Relayer removed: {} ({} remaining)", relayer, config.relayers.len());
+    
+    Ok(())
+}
+
+/// 启用/停用relayer
+fn process_set_relayer_status(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    relayer: Pubkey,
+    status: RelayerStatus,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    let info = config.find_relayer_mut(&relayer)
+        .ok_or(SettlementError::RelayerNotFound)?;
+    info.status = status;
+    save_config(&config, config_account)?;
+    
+    msg!("✅ Relayer {} status: {:?}", relayer, status);
+    
+    Ok(())
+}
+
+/// 提名新的admin（两步转移的第一步）
+fn process_propose_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_admin: Pubkey,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    // 提名当前admin视为撤销提名
+    config.pending_admin = if new_admin == config.admin {
+        None
+    } else {
+        Some(new_admin)
+    };
+    save_config(&config, config_account)?;
+    
+    match config.pending_admin {
+        Some(pending) => msg!("✅ Admin transfer proposed: {} -> {}", config.admin, pending),
+        None => msg!("✅ Pending admin transfer cancelled"),
+    }
+    
+    Ok(())
+}
+
+/// 接受admin权限（两步转移的第二步）
+fn process_accept_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let new_admin = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    
+    if !new_admin.is_signer {
+        msg!("Error: Pending admin is not signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    
+    if config.pending_admin != Some(*new_admin.key) {
+        msg!("Error: {} is not the pending admin", new_admin.key);
+        return Err(SettlementError::UnauthorizedAdmin.into());
+    }
+    
+    let previous = config.admin;
+    config.admin = *new_admin.key;
+    config.pending_admin = None;
+    save_config(&config, config_account)?;
+    
+    msg!("✅ Admin transferred: {} -> {}", previous, config.admin);
+    
+    Ok(())
+}
+
+/// 暂停/恢复（全局或单个市场）
+fn process_set_paused(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: Option<u16>,
+    paused: bool,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    match market {
+        None => {
+            config.paused = paused;
+            msg!("✅ Program {}", if paused { "paused" } else { "unpaused" });
+        }
+        Some(market_id) => {
+            if market_id >= ProgramConfig::MAX_MARKETS {
+                msg!("Error: Market id {} exceeds max {}", market_id, ProgramConfig::MAX_MARKETS);
+                return Err(ProgramError::InvalidArgument);
+            }
+            config.set_market_paused(market_id, paused);
+            msg!("✅ Market {} {}", market_id, if paused { "paused" } else { "unpaused" });
+        }
+    }
+    
+    save_config(&config, config_account)
+}
+
+/// 验证trade包含在batch中
+fn process_verify_trade_inclusion(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    trade: CompleteTrade,
+    proof: MerkleProof,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let batch_account = next_account_info(account_iter)?;
+    
+    if batch_account.owner != program_id {
+        msg!("Error: SettlementBatch owner mismatch");
+        return Err(ProgramError::IllegalOwner);
+    }
+    
+    let batch = SettlementBatch::try_from_slice(&batch_account.data.borrow())
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    if batch.discriminator != SettlementBatch::DISCRIMINATOR {
+        msg!("Error: Not a SettlementBatch account");
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    if proof.leaf_count != batch.trade_count {
+        msg!("Error: Proof leaf count {} != batch trade count {}",
+            proof.leaf_count, batch.trade_count);
+        return Err(SettlementError::InvalidMerkleProof.into());
+    }
+    
+    let leaf = hash_trade(&trade)?;
+    if !verify_proof(&batch.merkle_root, &leaf, &proof) {
+        msg!("Error: Trade {} not included in batch", trade.id);
+        return Err(SettlementError::InvalidMerkleProof.into());
+    }
+    
+    msg!("✅ Trade {} included in batch {} at index {}",
+        trade.id, format_batch_id(&batch.batch_id), proof.leaf_index);
+    
+    Ok(())
+}
+
+/// 设置是否允许engine_seq跳号
+fn process_set_allow_sequence_gaps(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    allow_gaps: bool,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    config.allow_seq_gaps = allow_gaps;
+    save_config(&config, config_account)?;
+    
+    msg!("✅ Sequence gaps {}", if allow_gaps { "allowed" } else { "disallowed" });
+    
+    Ok(())
+}
+
+/// 登记新市场（market_id按登记顺序分配）
+fn process_register_market(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    symbol: String,
+    params: MarketParams,
+    limits: MarketLimits,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let market_account = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    let stats_account = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    if symbol.is_empty() || symbol.len() > Market::MAX_SYMBOL_LEN {
+        msg!("Error: Market symbol must be 1-{} bytes", Market::MAX_SYMBOL_LEN);
+        return Err(SettlementError::InvalidMarketParams.into());
+    }
+    
+    params.validate()?;
+    limits.validate()?;
+    
+    if config.market_count >= ProgramConfig::MAX_MARKETS {
+        msg!("Error: Market registry is full ({})", ProgramConfig::MAX_MARKETS);
+        return Err(SettlementError::TooManyMarkets.into());
+    }
+    
+    let (expected_pda, bump) = Pubkey::find_program_address(
+        &[b"market", symbol.as_bytes()],
+        program_id,
+    );
+    
+    if market_account.key != &expected_pda {
+        msg!("Error: Market PDA mismatch. Expected {}, got {}",
+            expected_pda, market_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    if is_initialized_account(program_id, market_account, Market::DISCRIMINATOR) {
+        msg!("Error: Market {} already registered", symbol);
+        return Err(SettlementError::AccountAlreadyExists.into());
+    }
+    
+    msg!("Creating Market PDA...");
+    create_pda_account(
+        program_id,
+        admin,
+        market_account,
+        system_program,
+        Market::SIZE,
+        &[b"market", symbol.as_bytes(), &[bump]],
+    )?;
+    
+    let now = solana_program::clock::Clock::get()?.unix_timestamp * 1000;
+    let market = Market::new(symbol, config.market_count, bump, params, limits, now);
+    market.serialize(&mut &mut market_account.data.borrow_mut()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    let (expected_stats_pda, stats_bump) = Pubkey::find_program_address(
+        &[b"market_stats", market.symbol.as_bytes()],
+        program_id,
+    );
+    
+    if stats_account.key != &expected_stats_pda {
+        msg!("Error: MarketStats PDA mismatch. Expected {}, got {}",
+            expected_stats_pda, stats_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    msg!("Creating MarketStats PDA...");
+    create_pda_account(
+        program_id,
+        admin,
+        stats_account,
+        system_program,
+        MarketStats::SIZE,
+        &[b"market_stats", market.symbol.as_bytes(), &[stats_bump]],
+    )?;
+    
+    MarketStats::new(market.market_id, stats_bump)
+        .serialize(&mut &mut stats_account.data.borrow_mut()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    config.market_count += 1;
+    save_config(&config, config_account)?;
+    
+    msg!("✅ Market registered: {} (id {}, decimals {}/{}, multiplier_e6 {})",
+        market.symbol, market.market_id, params.base_decimals, params.quote_decimals,
+        params.contract_multiplier_e6);
+    
+    Ok(())
+}
+
+/// 修改市场的tick/lot/最大杠杆
+fn process_update_market_limits(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    symbol: &str,
+    limits: MarketLimits,
+) -> ProgramResult {
+    limits.validate()?;
+    
+    update_market(program_id, accounts, symbol, |market| market.limits = limits)?;
+    
+    msg!("✅ Market {} limits: tick {}, lot {}, max leverage {}x",
+        symbol, limits.tick_size, limits.lot_size, limits.max_leverage);
+    
+    Ok(())
+}
+
+/// 上架/下架市场
+fn process_set_market_status(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    symbol: &str,
+    status: MarketStatus,
+) -> ProgramResult {
+    update_market(program_id, accounts, symbol, |market| market.status = status)?;
+    
+    msg!("✅ Market {} status: {:?}", symbol, status);
+    
+    Ok(())
+}
+
+/// 读取Market（admin签名），应用update后写回
+fn update_market(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    symbol: &str,
+    update: impl FnOnce(&mut Market),
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let market_account = next_account_info(account_iter)?;
+    
+    let config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    let mut market = load_market(program_id, market_account, symbol)?;
+    update(&mut market);
+    
+    market.serialize(&mut &mut market_account.data.borrow_mut()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    Ok(())
+}
+
+/// 设置手续费档位表（不存在时创建FeeSchedule）
+fn process_set_fee_schedule(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    tiers: Vec<FeeTier>,
+    tolerance_e6: u64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    let fee_schedule_account = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    
+    let config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    FeeSchedule::validate_tiers(&tiers)?;
+    
+    let (expected_pda, bump) = Pubkey::find_program_address(&[b"fee_schedule"], program_id);
+    
+    if fee_schedule_account.key != &expected_pda {
+        msg!("Error: FeeSchedule PDA mismatch. Expected {}, got {}",
+            expected_pda, fee_schedule_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    if fee_schedule_account.owner != program_id {
+        msg!("Creating FeeSchedule PDA...");
+        create_pda_account(
+            program_id,
+            admin,
+            fee_schedule_account,
+            system_program,
+            FeeSchedule::SIZE,
+            &[b"fee_schedule", &[bump]],
+        )?;
+    }
+    
+    let fee_schedule = FeeSchedule {
+        discriminator: FeeSchedule::DISCRIMINATOR,
+        version: FeeSchedule::VERSION,
+        bump,
+        reserved: [0; 6],
+        tolerance_e6,
+        updated_at: solana_program::clock::Clock::get()?.unix_timestamp * 1000,
+        tiers,
+    };
+    // 按最大容量分配，档位减少时先清零尾部
+    let mut data = fee_schedule_account.data.borrow_mut();
+    data.fill(0);
+    fee_schedule.serialize(&mut &mut data[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    for tier in &fee_schedule.tiers {
+        msg!("  Tier ≥ {} USDC: taker {}, maker {}", tier.min_volume_e6, tier.taker_rate_e5, tier.maker_rate_e5);
+    }
+    msg!("✅ FeeSchedule set ({} tiers, tolerance {})", fee_schedule.tiers.len(), tolerance_e6);
+    
+    Ok(())
+}
+
+/// 设置用户的推荐人（用户签名，只能设置一次）
+fn process_set_referrer(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    referrer: Pubkey,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let wallet = next_account_info(account_iter)?;
+    let user_account = next_account_info(account_iter)?;
+    let referrer_account = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    // accounts[4]为ProgramConfig，只用于process_instruction中的全局暂停检查
+    
+    if !wallet.is_signer {
+        msg!("Error: Wallet is not signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    
+    if referrer == Pubkey::default() || referrer == *wallet.key {
+        msg!("Error: Invalid referrer {} for {}", referrer, wallet.key);
+        return Err(SettlementError::InvalidReferrer.into());
+    }
+    
+    check_user_settlement(program_id, wallet, user_account, system_program, wallet.key)?;
+    
+    if let Some(current) = UserSettlement::load(&user_account.data.borrow())?.referrer() {
+        msg!("Error: {} already referred by {}", wallet.key, current);
+        return Err(SettlementError::InvalidReferrer.into());
+    }
+    
+    if referrer_account.owner != program_id {
+        let (expected_pda, bump) = Pubkey::find_program_address(
+            &[b"referrer_stats", referrer.as_ref()],
+            program_id,
+        );
+        
+        if referrer_account.key != &expected_pda {
+            msg!("Error: ReferrerStats PDA mismatch. Expected {}, got {}",
+                expected_pda, referrer_account.key);
+            return Err(SettlementError::InvalidSettlementAccount.into());
+        }
+        
+        msg!("Creating ReferrerStats PDA for {}...", referrer);
+        create_pda_account(
+            program_id,
+            wallet,
+            referrer_account,
+            system_program,
+            ReferrerStats::SIZE,
+            &[b"referrer_stats", referrer.as_ref(), &[bump]],
+        )?;
+        
+        *ReferrerStats::load_mut(&mut referrer_account.data.borrow_mut())? = ReferrerStats::new(referrer, bump);
+    } else {
+        check_referrer_stats(program_id, referrer_account, &referrer)?;
+    }
+    
+    let mut referrer_data = referrer_account.data.borrow_mut();
+    let referrer_stats = ReferrerStats::load_mut(&mut referrer_data)?;
+    referrer_stats.referred_users = referrer_stats.referred_users.checked_add(1)
+        .ok_or(SettlementError::ArithmeticOverflow)?;
+    
+    UserSettlement::load_mut(&mut user_account.data.borrow_mut())?.referrer = referrer;
+    
+    msg!("✅ {} referred by {} (referred users: {})", wallet.key, referrer, referrer_stats.referred_users);
+    
+    Ok(())
+}
+
+/// 设置推荐人分成比例
+fn process_set_referral_share(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    share_bp: u16,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    if share_bp > ProgramConfig::MAX_REFERRAL_SHARE_BP {
+        msg!("Error: Referral share {} bp exceeds {}", share_bp, ProgramConfig::MAX_REFERRAL_SHARE_BP);
+        return Err(ProgramError::InvalidArgument);
+    }
+    
+    config.referral_share_bp = share_bp;
+    save_config(&config, config_account)?;
+    
+    msg!("✅ Referral share: {} bp", share_bp);
+    
+    Ok(())
+}
+
+/// 记录一个市场一个周期的资金费（每个周期只能记录一次）
+fn process_record_funding(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    epoch_id: u64,
+    symbol: &str,
+    payments: Vec<FundingPayment>,
+) -> ProgramResult {
+    msg!("Recording funding...");
+    msg!("  Market: {}, epoch: {}", symbol, epoch_id);
+    msg!("  Payments: {}", payments.len());
+    
+    let account_iter = &mut accounts.iter();
+    
+    let authority = next_account_info(account_iter)?;
+    let config_account = next_account_info(account_iter)?;
+    let epoch_account = next_account_info(account_iter)?;
+    let system_program = next_account_info(account_iter)?;
+    let market_account = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_relayer(&config, authority)?;
+    
+    let market = load_market(program_id, market_account, symbol)?;
+    check_markets_open(&config, std::slice::from_ref(&market))?;
+    
+    if payments.is_empty() {
+        msg!("Error: Funding epoch has no payments");
+        return Err(SettlementError::InvalidFunding.into());
+    }
+    
+    for (i, payment) in payments.iter().enumerate() {
+        if payments[..i].iter().any(|p| p.wallet == payment.wallet) {
+            msg!("Error: Duplicate funding payment for {}", payment.wallet);
+            return Err(SettlementError::InvalidFunding.into());
+        }
+    }
+    
+    // 支付和收取分别汇总，净额由保险基金吸收，不能超过tolerance
+    let mut total_paid_e6 = UsdcE6::ZERO;
+    let mut total_received_e6 = UsdcE6::ZERO;
+    for payment in &payments {
+        if payment.amount_e6.is_negative() {
+            total_received_e6 = total_received_e6.checked_sub(payment.amount_e6)?;
+        } else {
+            total_paid_e6 = total_paid_e6.checked_add(payment.amount_e6)?;
+        }
+    }
+    let imbalance_e6 = total_paid_e6.checked_sub(total_received_e6)?;
+    
+    if imbalance_e6.0.unsigned_abs() > config.funding_tolerance_e6 {
+        msg!("Error: Funding imbalance {} exceeds tolerance {}", imbalance_e6.0, config.funding_tolerance_e6);
+        return Err(SettlementError::FundingImbalance.into());
+    }
+    
+    let user_accounts = account_iter.as_slice();
+    if user_accounts.len() < payments.len() {
+        msg!("Error: Expected {} UserSettlement accounts, got {}", payments.len(), user_accounts.len());
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let user_accounts = &user_accounts[..payments.len()];
+    
+    // 创建FundingEpoch PDA（已存在说明该周期已记录）
+    let epoch_bytes = epoch_id.to_le_bytes();
+    let (expected_epoch_pda, epoch_bump) = Pubkey::find_program_address(
+        &[b"funding", symbol.as_bytes(), &epoch_bytes],
+        program_id,
+    );
+    
+    if epoch_account.key != &expected_epoch_pda {
+        msg!("Error: FundingEpoch PDA mismatch. Expected {}, got {}",
+            expected_epoch_pda, epoch_account.key);
+        return Err(SettlementError::InvalidSettlementAccount.into());
+    }
+    
+    if is_initialized_account(program_id, epoch_account, FundingEpoch::DISCRIMINATOR) {
+        msg!("Error: Funding epoch {} for {} already recorded", epoch_id, symbol);
+        return Err(SettlementError::AccountAlreadyExists.into());
+    }
+    
+    let clock = solana_program::clock::Clock::get()?;
+    let now = clock.unix_timestamp * 1000;
+    
+    msg!("Creating FundingEpoch PDA...");
+    create_pda_account(
+        program_id,
+        authority,
+        epoch_account,
+        system_program,
+        FundingEpoch::SIZE,
+        &[b"funding", symbol.as_bytes(), &epoch_bytes, &[epoch_bump]],
+    )?;
+    
+    let epoch = FundingEpoch {
+        discriminator: FundingEpoch::DISCRIMINATOR,
+        version: FundingEpoch::VERSION,
+        bump: epoch_bump,
+        market_id: market.market_id,
+        reserved: [0; 4],
+        epoch_id,
+        payment_count: payments.len() as u32,
+        total_paid_e6,
+        total_received_e6,
+        imbalance_e6,
+        relayer: *authority.key,
+        slot: clock.slot,
+        recorded_at: now,
+    };
+    epoch.serialize(&mut &mut epoch_account.data.borrow_mut()[..])
+        .map_err(|_| SettlementError::SerializationError)?;
+    
+    if let Some(relayer) = config.find_relayer_mut(authority.key) {
+        relayer.last_used_at = now;
+    }
+    save_config(&config, config_account)?;
+    
+    for (payment, user_account) in payments.iter().zip(user_accounts) {
+        check_user_settlement(program_id, authority, user_account, system_program, &payment.wallet)?;
+        
+        UserSettlement::load_mut(&mut user_account.data.borrow_mut())?
+            .record_funding(payment.amount_e6)?;
+        
+        msg!("FUNDING|wallet:{}|amount_e6:{}", payment.wallet, payment.amount_e6.0);
+    }
+    
+    msg!("✅ Funding recorded: {} epoch {} (paid {}, received {}, imbalance {})",
+        symbol, epoch_id, total_paid_e6.0, total_received_e6.0, imbalance_e6.0);
+    
+    Ok(())
+}
+
+/// 设置RecordFunding允许的净额误差
+fn process_set_funding_tolerance(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    tolerance_e6: u64,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    config.funding_tolerance_e6 = tolerance_e6;
+    save_config(&config, config_account)?;
+    
+    msg!("✅ Funding tolerance: {}", tolerance_e6);
+    
+    Ok(())
+}
+
+/// 设置RecordSettlement跳过的可选统计（仅admin）
+fn process_set_stats_tracking(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    stats_tracking: StatsTracking,
+) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    
+    let config_account = next_account_info(account_iter)?;
+    let admin = next_account_info(account_iter)?;
+    
+    let mut config = load_config(program_id, config_account)?;
+    check_admin(&config, admin)?;
+    
+    config.stats_tracking = stats_tracking;
+    save_config(&config, config_account)?;
+    
+    msg!("✅ Stats tracking: market stats {}, user market stats {}",
+        !stats_tracking.skip_market_stats, !stats_tracking.skip_user_market_stats);
     
     Ok(())
 }
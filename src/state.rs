@@ -3,8 +3,14 @@
 //! 定义Settlement Account的数据结构
 
 use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::{Pod, Zeroable};
 use solana_program::pubkey::Pubkey;
 
+use crate::{
+    amount::{div_round, PriceE6, QtyE6, Rounding, UsdcE6, WideUsdcE6, NOTIONAL_ROUNDING},
+    error::SettlementError,
+};
+
 /// Side枚举：Buy或Sell
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Side {
@@ -12,6 +18,16 @@ pub enum Side {
     Sell,
 }
 
+impl Side {
+    /// 对手方方向（maker的方向与taker_side相反）
+    pub fn opposite(self) -> Self {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
 /// 完整的Trade数据（所有19个字段）
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub struct CompleteTrade {
@@ -20,9 +36,9 @@ pub struct CompleteTrade {
     pub market: String,                // "BTC-PERP"
     
     // === 价格和数量（e6格式） ===
-    pub price_e6: i64,                 // 105315000000 = 105315 USDC
-    pub qty_e6: i64,                   // 1000 = 0.001 BTC
-    pub notional_e6: i64,              // price * qty
+    pub price_e6: PriceE6,             // 105315000000 = 105315 USDC
+    pub qty_e6: QtyE6,                 // 1000 = 0.001 BTC
+    pub notional_e6: UsdcE6,           // 成交额，按市场参数计算，见MarketParams::notional
     
     // === 方向和时间 ===
     pub taker_side: Side,              // Buy/Sell
@@ -44,10 +60,10 @@ pub struct CompleteTrade {
     pub maker_leverage: u32,           // 20x
     
     // === 手续费 ===
-    pub taker_fee_e6: i64,             // 47391 = 0.047391 USDC
-    pub maker_fee_e6: i64,             // 15797 = 0.015797 USDC
-    pub fee_rate_taker_bp: u32,        // 45 bp = 0.045%
-    pub fee_rate_maker_bp: u32,        // 15 bp = 0.015%
+    pub taker_fee_e6: UsdcE6,          // 47391 = 0.047391 USDC
+    pub maker_fee_e6: UsdcE6,          // 15797 = 0.015797 USDC
+    pub fee_rate_taker_e5: u32,        // 45 = 0.045%（单位见FEE_RATE_DENOMINATOR）
+    pub fee_rate_maker_e5: i32,        // 15 = 0.015%，负数 = 返佣
 }
 
 /// 账户结算汇总
@@ -61,11 +77,206 @@ pub struct SettlementSummary {
     pub position_change_e6: i64,       // 持仓变化
 }
 
-/// 用户级Settlement统计账户（每个用户一个）
-/// PDA Seeds: [b"user_settlement", user_wallet.as_ref()]
+/// Relayer状态
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayerStatus {
+    Active,
+    Disabled,
+}
+
+/// 授权Relayer登记信息
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
-pub struct UserSettlement {
-    /// 账户类型标识符 "USRSETTL" = 0x55535253_4554544c
+pub struct RelayerInfo {
+    pub pubkey: Pubkey,                // Relayer公钥
+    pub status: RelayerStatus,         // Active/Disabled
+    pub label: String,                 // 备注，如 "engine-hk-1"（最多MAX_LABEL_LEN字节）
+    pub created_at: i64,               // 登记时间（毫秒）
+    pub last_used_at: i64,             // 最后一次提交settlement的时间（毫秒，0=从未使用）
+}
+
+impl RelayerInfo {
+    /// label最大长度（bytes）
+    pub const MAX_LABEL_LEN: usize = 32;
+    
+    /// 最大大小（bytes）
+    /// 32 + 1 + (4 + 32) + 8 + 8 = 85 bytes
+    pub const SIZE: usize = 85;
+    
+    /// 创建新的Active relayer
+    pub fn new(pubkey: Pubkey, label: String, created_at: i64) -> Self {
+        Self {
+            pubkey,
+            status: RelayerStatus::Active,
+            label,
+            created_at,
+            last_used_at: 0,
+        }
+    }
+}
+
+/// RecordSettlement可以跳过的统计
+///
+/// 跳过的统计不再需要对应的accounts，单笔交易可以容纳更多trades。
+/// 字段为"跳过"语义：旧ProgramConfig中这两个字节是全0的预留字段，即全部更新。
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatsTracking {
+    /// 不更新MarketStats
+    pub skip_market_stats: bool,
+    /// 不更新UserMarketStats（持仓和已实现盈亏也不再累计）
+    pub skip_user_market_stats: bool,
+}
+
+impl StatsTracking {
+    /// 更新全部统计
+    pub const ALL: Self = Self { skip_market_stats: false, skip_user_market_stats: false };
+    
+    /// 只更新UserSettlement、GlobalStats和ReferrerStats
+    pub const MINIMAL: Self = Self { skip_market_stats: true, skip_user_market_stats: true };
+}
+
+/// Program全局配置账户（全局唯一）
+/// PDA Seeds: [b"config"]
+///
+/// 保存admin和授权relayer列表，替代原先硬编码的AUTHORIZED_RELAYER，
+/// 轮换relayer无需重新部署program。
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct ProgramConfig {
+    /// 账户类型标识符 "SETLCONF" = 0x5345544c_434f4e46
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 已登记的市场数量（下一个Market的market_id）
+    pub market_count: u16,
+    
+    /// 推荐人分成：被推荐用户taker手续费的比例（bp，1/10_000）
+    pub referral_share_bp: u16,
+    
+    /// RecordSettlement跳过的可选统计（SetStatsTracking设置，默认全部更新）
+    pub stats_tracking: StatsTracking,
+    
+    /// 管理员（可修改配置）
+    pub admin: Pubkey,
+    
+    /// 待接受的新管理员（ProposeAdmin设置，AcceptAdmin生效）
+    pub pending_admin: Option<Pubkey>,
+    
+    /// 全局暂停（暂停RecordSettlement和InitializeUser）
+    pub paused: bool,
+    
+    /// 按市场暂停的bitmap（bit i = market_id i）
+    pub paused_markets: [u8; 32],
+    
+    /// 已结算的最大engine_seq（0 = 尚未结算任何trade，engine_seq从1开始）
+    pub last_engine_seq: u64,
+    
+    /// 允许engine_seq跳号（仍要求严格递增）
+    pub allow_seq_gaps: bool,
+    
+    /// RecordFunding允许的净额误差（USDC, e6），由保险基金吸收的取整误差
+    pub funding_tolerance_e6: u64,
+    
+    /// 授权的Relayer登记表（最多MAX_RELAYERS个）
+    pub relayers: Vec<RelayerInfo>,
+}
+
+impl ProgramConfig {
+    /// 账户类型标识符 "SETLCONF"
+    pub const DISCRIMINATOR: u64 = 0x5345544c_434f4e46;
+    
+    /// 当前版本
+    pub const VERSION: u8 = 1;
+    
+    /// 最大relayer数量
+    pub const MAX_RELAYERS: usize = 8;
+    
+    /// paused_markets bitmap可容纳的市场数量
+    pub const MAX_MARKETS: u16 = 256;
+    
+    /// referral_share_bp上限（100%）
+    pub const MAX_REFERRAL_SHARE_BP: u16 = 10_000;
+    
+    /// 最大大小（bytes），按MAX_RELAYERS分配
+    /// 8 + 1 + 1 + 2 + 2 + 2 + 32 + 33 + 1 + 32 + 8 + 1 + 8 + (4 + 85*8) = 815 bytes
+    pub const SIZE: usize = 815;
+    
+    /// 创建新的ProgramConfig
+    pub fn new(admin: Pubkey, bump: u8, relayers: Vec<RelayerInfo>) -> Self {
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            market_count: 0,
+            referral_share_bp: 0,
+            stats_tracking: StatsTracking::ALL,
+            admin,
+            pending_admin: None,
+            paused: false,
+            paused_markets: [0; 32],
+            last_engine_seq: 0,
+            allow_seq_gaps: false,
+            funding_tolerance_e6: 0,
+            relayers,
+        }
+    }
+    
+    /// 查找relayer登记信息
+    pub fn find_relayer(&self, key: &Pubkey) -> Option<&RelayerInfo> {
+        self.relayers.iter().find(|r| &r.pubkey == key)
+    }
+    
+    /// 查找relayer登记信息（可变）
+    pub fn find_relayer_mut(&mut self, key: &Pubkey) -> Option<&mut RelayerInfo> {
+        self.relayers.iter_mut().find(|r| &r.pubkey == key)
+    }
+    
+    /// 市场是否被单独暂停
+    pub fn is_market_paused(&self, market_id: u16) -> bool {
+        market_id < Self::MAX_MARKETS
+            && self.paused_markets[market_id as usize / 8] & (1 << (market_id % 8)) != 0
+    }
+    
+    /// 设置市场暂停状态（market_id需小于MAX_MARKETS）
+    pub fn set_market_paused(&mut self, market_id: u16, paused: bool) {
+        let byte = &mut self.paused_markets[market_id as usize / 8];
+        let mask = 1 << (market_id % 8);
+        if paused {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+    
+    /// 是否为已启用的授权relayer
+    pub fn is_relayer(&self, key: &Pubkey) -> bool {
+        self.find_relayer(key)
+            .map(|r| r.status == RelayerStatus::Active)
+            .unwrap_or(false)
+    }
+}
+
+/// Batch ID：UUID字符串或16字节二进制UUID
+///
+/// 两种形式对应同一个16字节UUID，因此指向同一个SettlementBatch PDA。
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub enum BatchId {
+    /// "xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx"（36字符）
+    Uuid(String),
+    /// 16字节二进制UUID（节省20字节指令数据）
+    Binary([u8; 16]),
+}
+
+/// Settlement批次记录（每个batch一个，用于幂等）
+/// PDA Seeds: [b"settlement", batch_uuid_bytes]
+///
+/// 同一batch重复提交时PDA已存在，整笔交易失败，UserSettlement统计不会被重复累加。
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct SettlementBatch {
+    /// 账户类型标识符 "SETLBTCH" = 0x5345544c_42544348
     pub discriminator: u64,
     
     /// 数据版本
@@ -77,119 +288,1674 @@ pub struct UserSettlement {
     /// 预留字段（对齐）
     pub reserved: [u8; 6],
     
+    /// Batch ID（16字节UUID）
+    pub batch_id: [u8; 16],
+    
+    /// 批次数据hash：sha256(borsh(trades))
+    pub batch_hash: [u8; 32],
+    
+    /// trades的Merkle root（见merkle模块），用于单笔trade的inclusion proof
+    pub merkle_root: [u8; 32],
+    
+    // === 批次汇总 ===
+    pub trade_count: u32,              // trade数量
+    pub total_volume_e6: UsdcE6,       // 总成交额
+    pub total_fees_e6: UsdcE6,         // 总手续费（taker + maker）
+    
+    // === 提交信息 ===
+    pub relayer: Pubkey,               // 提交的relayer
+    pub slot: u64,                     // 记录时的slot
+    pub recorded_at: i64,              // 记录时间（毫秒）
+}
+
+impl SettlementBatch {
+    /// 账户类型标识符 "SETLBTCH"
+    pub const DISCRIMINATOR: u64 = 0x5345544c_42544348;
+    
+    /// 当前版本
+    pub const VERSION: u8 = 1;
+    
+    /// 固定大小（bytes）
+    /// 8 + 1 + 1 + 6 + 16 + 32 + 32 + 4 + 8 + 8 + 32 + 8 + 8 = 164 bytes
+    pub const SIZE: usize = 164;
+}
+
+/// 单个账户的资金费
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FundingPayment {
     /// 用户钱包地址
     pub wallet: Pubkey,
     
-    // === 交易次数统计 ===
-    pub total_trades: u64,             // 总交易次数
-    pub maker_trades: u64,             // 作为maker的次数
-    pub taker_trades: u64,             // 作为taker的次数
+    /// 资金费（USDC, e6，正数 = 支付，负数 = 收取）
+    pub amount_e6: UsdcE6,
+}
+
+/// 资金费结算记录（每个市场每个资金费周期一个，用于幂等）
+/// PDA Seeds: [b"funding", market_symbol, epoch_id.to_le_bytes()]
+///
+/// 同一周期重复提交时PDA已存在，整笔交易失败，UserSettlement资金费不会被重复累加。
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct FundingEpoch {
+    /// 账户类型标识符 "FUNDEPOC" = 0x46554e44_45504f43
+    pub discriminator: u64,
     
-    // === 交易量统计（USDC, e6格式）===
-    pub total_volume_e6: i64,          // 总交易量
-    pub maker_volume_e6: i64,          // 作为maker的交易量
-    pub taker_volume_e6: i64,          // 作为taker的交易量
+    /// 数据版本
+    pub version: u8,
     
-    // === 手续费统计（USDC, e6格式）===
-    pub total_fees_e6: i64,            // 总手续费（净支出，正数=支付）
-    pub maker_fees_e6: i64,            // maker手续费（负数=收入）
-    pub taker_fees_e6: i64,            // taker手续费（正数=支付）
+    /// PDA bump seed
+    pub bump: u8,
     
-    // === 时间戳 ===
-    pub first_trade_ts: i64,           // 首次交易时间（毫秒）
-    pub last_trade_ts: i64,            // 最后交易时间（毫秒）
+    /// 市场ID（Market.market_id）
+    pub market_id: u16,
     
-    // === 市场统计 ===
-    pub btc_perp_trades: u64,          // BTC-PERP交易次数
-    pub eth_perp_trades: u64,          // ETH-PERP交易次数
-    pub sol_perp_trades: u64,          // SOL-PERP交易次数
+    /// 预留字段（对齐）
+    pub reserved: [u8; 4],
+    
+    /// 资金费周期ID
+    pub epoch_id: u64,
+    
+    // === 周期汇总 ===
+    pub payment_count: u32,            // 资金费记录数
+    pub total_paid_e6: UsdcE6,         // 支付合计（正数）
+    pub total_received_e6: UsdcE6,     // 收取合计（正数）
+    pub imbalance_e6: UsdcE6,          // 净额（支付 - 收取），由保险基金吸收
+    
+    // === 提交信息 ===
+    pub relayer: Pubkey,               // 提交的relayer
+    pub slot: u64,                     // 记录时的slot
+    pub recorded_at: i64,              // 记录时间（毫秒）
+}
+
+impl FundingEpoch {
+    /// 账户类型标识符 "FUNDEPOC"
+    pub const DISCRIMINATOR: u64 = 0x46554e44_45504f43;
+    
+    /// 当前版本
+    pub const VERSION: u8 = 1;
+    
+    /// 固定大小（bytes）
+    /// 8 + 1 + 1 + 2 + 4 + 8 + 4 + 8 + 8 + 8 + 32 + 8 + 8 = 100 bytes
+    pub const SIZE: usize = 100;
+}
+
+/// 全交易所汇总统计（全局唯一）
+/// PDA Seeds: [b"global_stats"]
+///
+/// 由InitializeConfig创建，InitializeUser和RecordSettlement更新。
+/// 成交额和手续费为全交易所累计值，与MarketStats一样使用128位整数。
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GlobalStats {
+    /// 账户类型标识符 "GLBSTATS" = 0x474c4253_54415453
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 预留字段（对齐）
+    pub reserved: [u8; 6],
+    
+    // === 累计统计 ===
+    pub total_batches: u64,            // 已记录的batch数
+    pub total_trades: u64,             // 总成交笔数
+    pub total_volume_e6: u128,         // 总成交额（USDC, e6）
+    pub total_taker_fees_e6: i128,     // taker手续费合计
+    pub total_maker_fees_e6: i128,     // maker手续费合计（返佣为负）
+    pub total_users: u64,              // 已初始化的UserSettlement数
+    
+    // === 最近的batch ===
+    pub last_batch_id: [u8; 16],       // 最近记录的batch ID（16字节UUID）
+    pub last_batch_at: i64,            // 最近记录时间（毫秒）
     
     // === 预留扩展字段 ===
     pub reserved_stats: [u64; 8],      // 未来扩展用
 }
 
-impl UserSettlement {
-    /// 账户类型标识符 "USRSETTL"
-    pub const DISCRIMINATOR: u64 = 0x55535253_4554544c;
+impl GlobalStats {
+    /// 账户类型标识符 "GLBSTATS"
+    pub const DISCRIMINATOR: u64 = 0x474c4253_54415453;
     
     /// 当前版本
     pub const VERSION: u8 = 1;
     
     /// 固定大小（bytes）
-    /// 8 + 1 + 1 + 6 + 32 + 8*3 + 8*3 + 8*3 + 8*2 + 8*3 + 8*8 = 224 bytes
-    pub const SIZE: usize = 224;
+    /// 8 + 1 + 1 + 6 + 8 + 8 + 16 + 16 + 16 + 8 + 16 + 8 + 8*8 = 176 bytes
+    pub const SIZE: usize = 176;
     
-    /// 创建新的UserSettlement（初始状态）
-    pub fn new(wallet: Pubkey, bump: u8, first_trade_ts: i64) -> Self {
+    /// 创建新的GlobalStats（全部为0）
+    pub fn new(bump: u8) -> Self {
         Self {
             discriminator: Self::DISCRIMINATOR,
             version: Self::VERSION,
             bump,
             reserved: [0; 6],
-            wallet,
+            total_batches: 0,
             total_trades: 0,
-            maker_trades: 0,
-            taker_trades: 0,
             total_volume_e6: 0,
-            maker_volume_e6: 0,
-            taker_volume_e6: 0,
-            total_fees_e6: 0,
-            maker_fees_e6: 0,
-            taker_fees_e6: 0,
-            first_trade_ts,
-            last_trade_ts: first_trade_ts,
-            btc_perp_trades: 0,
-            eth_perp_trades: 0,
-            sol_perp_trades: 0,
+            total_taker_fees_e6: 0,
+            total_maker_fees_e6: 0,
+            total_users: 0,
+            last_batch_id: [0; 16],
+            last_batch_at: 0,
             reserved_stats: [0; 8],
         }
     }
     
-    /// 更新统计（作为taker）
-    pub fn update_as_taker(&mut self, trade: &CompleteTrade) {
-        self.total_trades += 1;
-        self.taker_trades += 1;
+    /// 累加一个batch的trades
+    pub fn record_batch(
+        &mut self,
+        batch_id: [u8; 16],
+        trades: &[CompleteTrade],
+        recorded_at: i64,
+    ) -> Result<(), SettlementError> {
+        for trade in trades {
+            let volume = u128::try_from(trade.notional_e6.0)
+                .map_err(|_| SettlementError::ArithmeticOverflow)?;
+            
+            self.total_volume_e6 = self.total_volume_e6.checked_add(volume)
+                .ok_or(SettlementError::ArithmeticOverflow)?;
+            self.total_taker_fees_e6 = self.total_taker_fees_e6.checked_add(trade.taker_fee_e6.0 as i128)
+                .ok_or(SettlementError::ArithmeticOverflow)?;
+            self.total_maker_fees_e6 = self.total_maker_fees_e6.checked_add(trade.maker_fee_e6.0 as i128)
+                .ok_or(SettlementError::ArithmeticOverflow)?;
+        }
         
-        let volume = (trade.price_e6 as i128 * trade.qty_e6 as i128 / 1_000_000) as i64;
-        self.total_volume_e6 += volume;
-        self.taker_volume_e6 += volume;
+        self.total_trades = checked_add_u64(self.total_trades, trades.len() as u64)?;
+        self.total_batches = checked_add_u64(self.total_batches, 1)?;
+        self.last_batch_id = batch_id;
+        self.last_batch_at = recorded_at;
+        
+        Ok(())
+    }
+}
+
+/// 费率单位：1/100_000（trade中的 `fee_rate_*_e5` 字段使用同一单位，45 = 0.045%）
+pub const FEE_RATE_DENOMINATOR: i64 = 100_000;
+
+/// 费率档位
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeTier {
+    /// 适用的最低30天成交量（USDC, e6，含）
+    pub min_volume_e6: UsdcE6,
+    
+    /// taker费率（FEE_RATE_DENOMINATOR单位）
+    pub taker_rate_e5: u32,
+    
+    /// maker费率（FEE_RATE_DENOMINATOR单位，负数 = 返佣）
+    pub maker_rate_e5: i32,
+}
+
+/// 手续费档位表（全局唯一）
+/// PDA Seeds: [b"fee_schedule"]
+///
+/// 由admin通过SetFeeSchedule设置。RecordSettlement按用户最近30天成交量选择档位，
+/// 校验trade的费率与档位一致、手续费与 notional * rate / FEE_RATE_DENOMINATOR 的差不超过tolerance_e6。
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FeeSchedule {
+    /// 账户类型标识符 "FEESCHED" = 0x46454553_43484544
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 预留字段（对齐）
+    pub reserved: [u8; 6],
+    
+    /// 手续费允许的取整误差（USDC, e6）
+    pub tolerance_e6: u64,
+    
+    /// 最后修改时间（毫秒）
+    pub updated_at: i64,
+    
+    /// 费率档位，按min_volume_e6严格递增，第一个档位的min_volume_e6为0
+    pub tiers: Vec<FeeTier>,
+}
+
+impl FeeSchedule {
+    /// 账户类型标识符 "FEESCHED"
+    pub const DISCRIMINATOR: u64 = 0x46454553_43484544;
+    
+    /// 当前版本
+    pub const VERSION: u8 = 1;
+    
+    /// 最大档位数
+    pub const MAX_TIERS: usize = 8;
+    
+    /// 选择档位使用的成交量窗口（天）
+    pub const VOLUME_WINDOW_DAYS: usize = 30;
+    
+    /// 最大大小（bytes）
+    /// 8 + 1 + 1 + 6 + 8 + 8 + (4 + 8 * 16) = 164 bytes
+    pub const SIZE: usize = 164;
+    
+    /// 校验档位表：1..=MAX_TIERS个档位，从0开始严格递增，taker + maker费率不为负
+    pub fn validate_tiers(tiers: &[FeeTier]) -> Result<(), SettlementError> {
+        if tiers.is_empty() || tiers.len() > Self::MAX_TIERS || tiers[0].min_volume_e6 != UsdcE6::ZERO {
+            return Err(SettlementError::InvalidFeeSchedule);
+        }
         
-        self.total_fees_e6 += trade.taker_fee_e6;
-        self.taker_fees_e6 += trade.taker_fee_e6;
+        if tiers.windows(2).any(|pair| pair[1].min_volume_e6 <= pair[0].min_volume_e6) {
+            return Err(SettlementError::InvalidFeeSchedule);
+        }
         
-        self.last_trade_ts = trade.ts_ms;
+        // maker返佣不能超过taker费率，否则交易所每笔成交净支出
+        if tiers.iter().any(|tier| tier.taker_rate_e5 as i64 + (tier.maker_rate_e5 as i64) < 0) {
+            return Err(SettlementError::InvalidFeeSchedule);
+        }
         
-        // 更新市场统计
-        self.update_market_stats(&trade.market);
+        Ok(())
     }
     
-    /// 更新统计（作为maker）
-    pub fn update_as_maker(&mut self, trade: &CompleteTrade) {
-        self.total_trades += 1;
-        self.maker_trades += 1;
-        
-        let volume = (trade.price_e6 as i128 * trade.qty_e6 as i128 / 1_000_000) as i64;
-        self.total_volume_e6 += volume;
-        self.maker_volume_e6 += volume;
+    /// 30天成交量对应的档位
+    pub fn tier_for(&self, volume_30d: UsdcE6) -> Option<&FeeTier> {
+        self.tiers.iter().rev().find(|tier| tier.min_volume_e6 <= volume_30d)
+    }
+    
+    /// 按档位计算手续费：notional * rate / FEE_RATE_DENOMINATOR（四舍五入）
+    pub fn fee_for(notional: UsdcE6, rate: i64) -> Result<UsdcE6, SettlementError> {
+        notional.checked_mul_div(rate, FEE_RATE_DENOMINATOR, Rounding::HalfAwayFromZero)
+    }
+    
+    /// 校验taker的费率和手续费（volume_30d为taker在该trade之前的30天成交量）
+    pub fn validate_taker_fee(&self, trade: &CompleteTrade, volume_30d: UsdcE6) -> Result<(), SettlementError> {
+        let tier = self.tier_for(volume_30d).ok_or(SettlementError::InvalidFeeSchedule)?;
+        self.validate_fee(trade, trade.fee_rate_taker_e5 as i64, trade.taker_fee_e6, tier.taker_rate_e5 as i64)
+    }
+    
+    /// 校验maker的费率和手续费（volume_30d为maker在该trade之前的30天成交量）
+    pub fn validate_maker_fee(&self, trade: &CompleteTrade, volume_30d: UsdcE6) -> Result<(), SettlementError> {
+        let tier = self.tier_for(volume_30d).ok_or(SettlementError::InvalidFeeSchedule)?;
+        self.validate_fee(trade, trade.fee_rate_maker_e5 as i64, trade.maker_fee_e6, tier.maker_rate_e5 as i64)
+    }
+    
+    fn validate_fee(
+        &self,
+        trade: &CompleteTrade,
+        trade_rate: i64,
+        fee: UsdcE6,
+        tier_rate: i64,
+    ) -> Result<(), SettlementError> {
+        if trade_rate != tier_rate {
+            return Err(SettlementError::FeeMismatch);
+        }
         
-        // Maker手续费通常是负数（收入）
-        self.total_fees_e6 += trade.maker_fee_e6;
-        self.maker_fees_e6 += trade.maker_fee_e6;
+        let expected = Self::fee_for(trade.notional_e6, tier_rate)?;
+        if fee.0.abs_diff(expected.0) > self.tolerance_e6 {
+            return Err(SettlementError::FeeMismatch);
+        }
         
-        self.last_trade_ts = trade.ts_ms;
+        Ok(())
+    }
+}
+
+/// 市场合约参数
+///
+/// price_e6按quote_decimals位小数表示，qty_e6按base_decimals位小数表示，
+/// 每张合约对应contract_multiplier_e6 / 1e6个基础资产：
+/// notional(USDC e6) = price * qty * multiplier_e6 / 10^(base_decimals + quote_decimals)
+///
+/// 标准合约（6位小数、乘数1）即 price * qty / 1e6。
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MarketParams {
+    /// 数量的小数位数
+    pub base_decimals: u8,
+    
+    /// 价格的小数位数
+    pub quote_decimals: u8,
+    
+    /// 合约乘数（e6，1_000_000 = 每张合约1个基础资产）
+    pub contract_multiplier_e6: u64,
+}
+
+impl MarketParams {
+    /// 小数位数上限（两者之和不超过36，10^36仍在i128范围内）
+    pub const MAX_DECIMALS: u8 = 18;
+    
+    /// 标准合约参数
+    pub const STANDARD: Self = Self {
+        base_decimals: 6,
+        quote_decimals: 6,
+        contract_multiplier_e6: 1_000_000,
+    };
+    
+    /// 校验参数范围
+    pub fn validate(&self) -> Result<(), SettlementError> {
+        if self.base_decimals > Self::MAX_DECIMALS
+            || self.quote_decimals > Self::MAX_DECIMALS
+            || self.contract_multiplier_e6 == 0
+            || self.contract_multiplier_e6 > i64::MAX as u64
+        {
+            return Err(SettlementError::InvalidMarketParams);
+        }
+        Ok(())
+    }
+    
+    /// 按市场参数计算成交额（USDC e6，按NOTIONAL_ROUNDING取整）
+    pub fn notional(&self, price: PriceE6, qty: QtyE6) -> Result<UsdcE6, SettlementError> {
+        let numerator = (price.0 as i128)
+            .checked_mul(qty.0 as i128)
+            .and_then(|n| n.checked_mul(self.contract_multiplier_e6 as i128))
+            .ok_or(SettlementError::ArithmeticOverflow)?;
+        let denominator = 10i128.pow(self.base_decimals as u32 + self.quote_decimals as u32);
         
-        // 更新市场统计
-        self.update_market_stats(&trade.market);
+        div_round(numerator, denominator, NOTIONAL_ROUNDING).map(UsdcE6)
     }
+}
+
+/// 市场交易限制（admin可通过UpdateMarketLimits修改）
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MarketLimits {
+    /// 最小价格变动单位，price_e6必须是其整数倍
+    pub tick_size: PriceE6,
+    
+    /// 最小数量单位，qty_e6必须是其整数倍
+    pub lot_size: QtyE6,
     
-    /// 更新市场统计
-    fn update_market_stats(&mut self, market: &str) {
-        match market {
-            "BTC-PERP" => self.btc_perp_trades += 1,
-            "ETH-PERP" => self.eth_perp_trades += 1,
-            "SOL-PERP" => self.sol_perp_trades += 1,
-            _ => {}, // 其他市场暂不统计
+    /// 最大杠杆倍数
+    pub max_leverage: u32,
+}
+
+impl MarketLimits {
+    /// 校验限制范围
+    pub fn validate(&self) -> Result<(), SettlementError> {
+        if !self.tick_size.is_positive() || !self.lot_size.is_positive() || self.max_leverage == 0 {
+            return Err(SettlementError::InvalidMarketParams);
         }
+        Ok(())
+    }
+    
+    /// trade是否满足tick/lot/杠杆限制
+    pub fn allows(&self, trade: &CompleteTrade) -> bool {
+        trade.price_e6.0 % self.tick_size.0 == 0
+            && trade.qty_e6.0 % self.lot_size.0 == 0
+            && (1..=self.max_leverage).contains(&trade.taker_leverage)
+            && (1..=self.max_leverage).contains(&trade.maker_leverage)
     }
 }
 
+/// 市场状态
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketStatus {
+    /// 正常结算
+    Active,
+    /// 已下架，RecordSettlement返回MarketDelisted
+    Delisted,
+}
+
+/// 市场登记信息（每个市场一个）
+/// PDA Seeds: [b"market", symbol.as_bytes()]
+///
+/// 由admin通过RegisterMarket创建，RecordSettlement只接受已登记市场的trades。
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct Market {
+    /// 账户类型标识符 "SETLMRKT" = 0x5345544c_4d524b54
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 市场ID（登记顺序，用于按市场暂停）
+    pub market_id: u16,
+    
+    /// 市场状态
+    pub status: MarketStatus,
+    
+    /// 预留字段（对齐）
+    pub reserved: [u8; 3],
+    
+    /// 市场名称，如 "BTC-PERP"（与trade.market一致）
+    pub symbol: String,
+    
+    /// 合约参数（登记后不可修改）
+    pub params: MarketParams,
+    
+    /// 交易限制
+    pub limits: MarketLimits,
+    
+    /// 登记时间（毫秒）
+    pub created_at: i64,
+}
+
+impl Market {
+    /// 账户类型标识符 "SETLMRKT"
+    pub const DISCRIMINATOR: u64 = 0x5345544c_4d524b54;
+    
+    /// 当前版本
+    pub const VERSION: u8 = 1;
+    
+    /// symbol最大长度（bytes）
+    pub const MAX_SYMBOL_LEN: usize = 16;
+    
+    /// 最大大小（bytes）
+    /// 8 + 1 + 1 + 2 + 1 + 3 + (4 + 16) + (1 + 1 + 8) + (8 + 8 + 4) + 8 = 74 bytes
+    pub const SIZE: usize = 74;
+    
+    /// 创建新的Market（Active）
+    pub fn new(
+        symbol: String,
+        market_id: u16,
+        bump: u8,
+        params: MarketParams,
+        limits: MarketLimits,
+        created_at: i64,
+    ) -> Self {
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            market_id,
+            status: MarketStatus::Active,
+            reserved: [0; 3],
+            symbol,
+            params,
+            limits,
+            created_at,
+        }
+    }
+    
+    /// 按本市场参数计算成交额
+    pub fn notional(&self, price: PriceE6, qty: QtyE6) -> Result<UsdcE6, SettlementError> {
+        self.params.notional(price, qty)
+    }
+}
+
+/// 市场级汇总统计账户（每个市场一个）
+/// PDA Seeds: [b"market_stats", symbol.as_bytes()]
+///
+/// 与Market一同由RegisterMarket创建，每次RecordSettlement按trades更新。
+/// 累计成交额/手续费是全交易所汇总，使用128位整数（i64约9.2万亿USDC，大市场数月即可达到）。
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MarketStats {
+    /// 账户类型标识符 "MKTSTATS" = 0x4d4b5453_54415453
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 市场ID（Market.market_id）
+    pub market_id: u16,
+    
+    /// 预留字段（对齐）
+    pub reserved: [u8; 4],
+    
+    // === 累计统计 ===
+    pub total_trades: u64,             // 总成交笔数
+    pub total_volume_e6: u128,         // 总成交额（USDC, e6）
+    pub total_fees_e6: i128,           // 总手续费（taker + maker，maker返佣为负）
+    
+    // === 最新成交 ===
+    pub last_price_e6: PriceE6,        // 最新成交价
+    pub last_engine_seq: u64,          // 最新成交的engine_seq
+    pub last_trade_ts: i64,            // 最新成交时间（毫秒）
+    
+    // === 当前周期（UTC自然日）===
+    pub period_start_ts: i64,          // 周期开始时间（毫秒）
+    pub period_high_e6: PriceE6,       // 周期内最高价
+    pub period_low_e6: PriceE6,        // 周期内最低价
+    
+    // === 预留扩展字段 ===
+    pub reserved_stats: [u64; 8],      // 未来扩展用
+}
+
+impl MarketStats {
+    /// 账户类型标识符 "MKTSTATS"
+    pub const DISCRIMINATOR: u64 = 0x4d4b5453_54415453;
+    
+    /// 当前版本
+    pub const VERSION: u8 = 1;
+    
+    /// 固定大小（bytes）
+    /// 8 + 1 + 1 + 2 + 4 + 8 + 16 + 16 + 8*3 + 8*3 + 8*8 = 168 bytes
+    pub const SIZE: usize = 168;
+    
+    /// 高/低价统计周期：1天（毫秒）
+    pub const PERIOD_MS: i64 = 86_400_000;
+    
+    /// 创建新的MarketStats（尚无成交）
+    pub fn new(market_id: u16, bump: u8) -> Self {
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            market_id,
+            reserved: [0; 4],
+            total_trades: 0,
+            total_volume_e6: 0,
+            total_fees_e6: 0,
+            last_price_e6: PriceE6::ZERO,
+            last_engine_seq: 0,
+            last_trade_ts: 0,
+            period_start_ts: 0,
+            period_high_e6: PriceE6::ZERO,
+            period_low_e6: PriceE6::ZERO,
+            reserved_stats: [0; 8],
+        }
+    }
+    
+    /// 按一笔成交更新统计
+    ///
+    /// 成交进入新的周期时重置高/低价；早于当前周期的成交不影响高/低价。
+    pub fn update(&mut self, trade: &CompleteTrade) -> Result<(), SettlementError> {
+        let volume = u128::try_from(trade.notional_e6.0)
+            .map_err(|_| SettlementError::ArithmeticOverflow)?;
+        let fees = trade.taker_fee_e6.0 as i128 + trade.maker_fee_e6.0 as i128;
+        
+        self.total_volume_e6 = self.total_volume_e6.checked_add(volume)
+            .ok_or(SettlementError::ArithmeticOverflow)?;
+        self.total_fees_e6 = self.total_fees_e6.checked_add(fees)
+            .ok_or(SettlementError::ArithmeticOverflow)?;
+        
+        let period_start_ts = trade.ts_ms - trade.ts_ms.rem_euclid(Self::PERIOD_MS);
+        if self.total_trades == 0 || period_start_ts > self.period_start_ts {
+            self.period_start_ts = period_start_ts;
+            self.period_high_e6 = trade.price_e6;
+            self.period_low_e6 = trade.price_e6;
+        } else if period_start_ts == self.period_start_ts {
+            self.period_high_e6 = self.period_high_e6.max(trade.price_e6);
+            self.period_low_e6 = self.period_low_e6.min(trade.price_e6);
+        }
+        
+        self.total_trades = checked_add_u64(self.total_trades, 1)?;
+        self.last_price_e6 = trade.price_e6;
+        self.last_engine_seq = trade.engine_seq;
+        self.last_trade_ts = trade.ts_ms;
+        
+        Ok(())
+    }
+}
+
+/// 用户级Settlement统计账户（每个用户一个）
+/// PDA Seeds: [b"user_settlement", user_wallet.as_ref()]
+///
+/// `#[repr(C)]` 布局与Borsh编码逐字节一致（字段自然对齐、无padding、little-endian），
+/// 链上通过 `load` / `load_mut` 直接在account数据上读写，无需反序列化。
+///
+/// v2在v1（224 bytes）末尾追加了按日成交量窗口；v3将累计成交量和手续费扩展为128位
+/// （字段位置随之后移）并追加资金费统计。旧版本account由RecordSettlement/RecordFunding/SetReferrer
+/// 按 `LegacyUserSettlement` 读出后扩容重写，新增字段初始化为0。
+#[repr(C)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Pod, Zeroable)]
+pub struct UserSettlement {
+    /// 账户类型标识符 "USRSETTL" = 0x55535253_4554544c
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 预留字段（对齐）
+    pub reserved: [u8; 6],
+    
+    /// 用户钱包地址
+    pub wallet: Pubkey,
+    
+    // === 交易次数统计 ===
+    pub total_trades: u64,             // 总交易次数
+    pub maker_trades: u64,             // 作为maker的次数
+    pub taker_trades: u64,             // 作为taker的次数
+    
+    // === 交易量统计（USDC, e6格式，v3起为128位，做市商的生命周期累计可能超出i64）===
+    pub total_volume_e6: WideUsdcE6,   // 总交易量
+    pub maker_volume_e6: WideUsdcE6,   // 作为maker的交易量
+    pub taker_volume_e6: WideUsdcE6,   // 作为taker的交易量
+    
+    // === 手续费统计（USDC, e6格式，v3起为128位）===
+    pub total_fees_e6: WideUsdcE6,     // 总手续费（净支出，正数=支付）
+    pub maker_fees_e6: WideUsdcE6,     // maker手续费（负数=收入）
+    pub taker_fees_e6: WideUsdcE6,     // taker手续费（正数=支付）
+    
+    // === 时间戳 ===
+    pub first_trade_ts: i64,           // 首次交易时间（毫秒）
+    pub last_trade_ts: i64,            // 最后交易时间（毫秒）
+    
+    // === 市场统计 ===
+    // 即v1的btc/eth/sol_perp_trades，按MARKET_TRADE_SYMBOLS的symbol计数，与market_id无关；
+    // 其他市场不在此计数，分市场统计见UserMarketStats
+    pub market_trades: [u64; 3],       // BTC-PERP / ETH-PERP / SOL-PERP交易次数
+    
+    // === 手续费收支（USDC, e6格式，128位，之前的成交不计入）===
+    pub fees_paid_e6: WideUsdcE6,      // 支付的手续费合计（taker手续费 + 正的maker手续费）
+    pub rebates_earned_e6: WideUsdcE6, // 获得的maker返佣合计（正数）
+    
+    // === 推荐人（占用原预留字段）===
+    pub referrer: Pubkey,              // 推荐人钱包（全0 = 无），由SetReferrer设置，设置后不可修改
+    
+    // === 已实现盈亏（USDC, e6格式，占用原预留字段，之前的成交不计入）===
+    pub realized_pnl_e6: UsdcE6,       // 各市场已实现盈亏合计（不含手续费）
+    
+    // === 预留扩展字段 ===
+    pub reserved_stats: [u64; 1],      // 未来扩展用
+    
+    // === 按日成交量（v2）===
+    // ring buffer：第day天（ts_ms / 86_400_000）的成交量在 daily_volume_e6[day % 30]
+    pub volume_day: i64,               // 最新bucket对应的天
+    pub daily_volume_e6: [UsdcE6; 30], // 最近30天每天的成交量（taker + maker）
+    
+    // === 资金费（v3，USDC, e6格式）===
+    pub funding_paid_e6: UsdcE6,       // 累计支付的资金费（正数）
+    pub funding_received_e6: UsdcE6,   // 累计收取的资金费（正数）
+}
+
+impl UserSettlement {
+    /// 账户类型标识符 "USRSETTL"
+    pub const DISCRIMINATOR: u64 = 0x55535253_4554544c;
+    
+    /// 当前版本
+    pub const VERSION: u8 = 3;
+    
+    /// 固定大小（bytes）
+    /// 8 + 1 + 1 + 6 + 32 + 8*3 + 16*3 + 16*3 + 8*2 + 8*3 + 16*2 + 32 + 8 + 8 + 8 + 8*30 + 8*2 = 552 bytes
+    pub const SIZE: usize = 552;
+    
+    /// v2 account大小（不含资金费统计）
+    /// v1 (224) + 8 + 8*30 = 472 bytes
+    pub const V2_SIZE: usize = 472;
+    
+    /// v1 account大小（不含按日成交量）
+    /// 8 + 1 + 1 + 6 + 32 + 8*3 + 8*3 + 8*3 + 8*2 + 8*3 + 8*8 = 224 bytes
+    pub const V1_SIZE: usize = 224;
+    
+    /// 按日成交量窗口的天数
+    pub const VOLUME_WINDOW_DAYS: usize = 30;
+    
+    /// 一天的毫秒数（按日bucket的粒度，UTC）
+    pub const DAY_MS: i64 = 86_400_000;
+    
+    /// market_trades各位置对应的市场（v1布局）
+    pub const MARKET_TRADE_SYMBOLS: [&'static str; 3] = ["BTC-PERP", "ETH-PERP", "SOL-PERP"];
+    
+    /// 以account数据构造只读视图（zero-copy）
+    pub fn load(data: &[u8]) -> Result<&Self, SettlementError> {
+        let bytes = data.get(..Self::SIZE).ok_or(SettlementError::InvalidSettlementAccount)?;
+        bytemuck::try_from_bytes(bytes).map_err(|_| SettlementError::InvalidSettlementAccount)
+    }
+    
+    /// 以account数据构造可写视图（zero-copy），修改直接写入account
+    pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, SettlementError> {
+        let bytes = data.get_mut(..Self::SIZE).ok_or(SettlementError::InvalidSettlementAccount)?;
+        bytemuck::try_from_bytes_mut(bytes).map_err(|_| SettlementError::InvalidSettlementAccount)
+    }
+    
+    /// 创建新的UserSettlement（初始状态）
+    pub fn new(wallet: Pubkey, bump: u8, first_trade_ts: i64) -> Self {
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            reserved: [0; 6],
+            wallet,
+            total_trades: 0,
+            maker_trades: 0,
+            taker_trades: 0,
+            total_volume_e6: WideUsdcE6::ZERO,
+            maker_volume_e6: WideUsdcE6::ZERO,
+            taker_volume_e6: WideUsdcE6::ZERO,
+            total_fees_e6: WideUsdcE6::ZERO,
+            maker_fees_e6: WideUsdcE6::ZERO,
+            taker_fees_e6: WideUsdcE6::ZERO,
+            first_trade_ts,
+            last_trade_ts: first_trade_ts,
+            market_trades: [0; 3],
+            fees_paid_e6: WideUsdcE6::ZERO,
+            rebates_earned_e6: WideUsdcE6::ZERO,
+            referrer: Pubkey::default(),
+            realized_pnl_e6: UsdcE6::ZERO,
+            reserved_stats: [0; 1],
+            volume_day: 0,
+            daily_volume_e6: [UsdcE6::ZERO; 30],
+            funding_paid_e6: UsdcE6::ZERO,
+            funding_received_e6: UsdcE6::ZERO,
+        }
+    }
+    
+    /// 更新统计（作为taker）
+    ///
+    /// 成交额取trade.notional_e6（RecordSettlement已按市场参数校验）
+    pub fn update_as_taker(&mut self, trade: &CompleteTrade) -> Result<(), SettlementError> {
+        let volume = trade.notional_e6;
+        
+        self.total_trades = checked_add_u64(self.total_trades, 1)?;
+        self.taker_trades = checked_add_u64(self.taker_trades, 1)?;
+        
+        self.total_volume_e6 = self.total_volume_e6.checked_add(volume)?;
+        self.taker_volume_e6 = self.taker_volume_e6.checked_add(volume)?;
+        self.record_daily_volume(trade.ts_ms, volume)?;
+        
+        self.total_fees_e6 = self.total_fees_e6.checked_add(trade.taker_fee_e6)?;
+        self.taker_fees_e6 = self.taker_fees_e6.checked_add(trade.taker_fee_e6)?;
+        self.fees_paid_e6 = self.fees_paid_e6.checked_add(trade.taker_fee_e6)?;
+        
+        self.last_trade_ts = trade.ts_ms;
+        
+        // 更新市场统计
+        self.update_market_stats(&trade.market)
+    }
+    
+    /// 更新统计（作为maker）
+    pub fn update_as_maker(&mut self, trade: &CompleteTrade) -> Result<(), SettlementError> {
+        let volume = trade.notional_e6;
+        
+        self.total_trades = checked_add_u64(self.total_trades, 1)?;
+        self.maker_trades = checked_add_u64(self.maker_trades, 1)?;
+        
+        self.total_volume_e6 = self.total_volume_e6.checked_add(volume)?;
+        self.maker_volume_e6 = self.maker_volume_e6.checked_add(volume)?;
+        self.record_daily_volume(trade.ts_ms, volume)?;
+        
+        // Maker手续费为负数时是返佣（收入）
+        self.total_fees_e6 = self.total_fees_e6.checked_add(trade.maker_fee_e6)?;
+        self.maker_fees_e6 = self.maker_fees_e6.checked_add(trade.maker_fee_e6)?;
+        if trade.maker_fee_e6.is_negative() {
+            self.rebates_earned_e6 = self.rebates_earned_e6.checked_sub(trade.maker_fee_e6)?;
+        } else {
+            self.fees_paid_e6 = self.fees_paid_e6.checked_add(trade.maker_fee_e6)?;
+        }
+        
+        self.last_trade_ts = trade.ts_ms;
+        
+        // 更新市场统计
+        self.update_market_stats(&trade.market)
+    }
+    
+    /// 累加一笔资金费（正数 = 支付，负数 = 收取）
+    pub fn record_funding(&mut self, amount: UsdcE6) -> Result<(), SettlementError> {
+        if amount.is_negative() {
+            self.funding_received_e6 = self.funding_received_e6.checked_sub(amount)?;
+        } else {
+            self.funding_paid_e6 = self.funding_paid_e6.checked_add(amount)?;
+        }
+        Ok(())
+    }
+    
+    /// 推荐人（未设置时为None）
+    pub fn referrer(&self) -> Option<Pubkey> {
+        (self.referrer != Pubkey::default()).then_some(self.referrer)
+    }
+    
+    /// 计算截至now_ms（含当天）最近days天的成交量，days最多为VOLUME_WINDOW_DAYS
+    ///
+    /// 例如30天费率档位：`trailing_volume(now_ms, 30)`。
+    pub fn trailing_volume(&self, now_ms: i64, days: usize) -> Result<UsdcE6, SettlementError> {
+        let today = now_ms.div_euclid(Self::DAY_MS);
+        let days = days.min(Self::VOLUME_WINDOW_DAYS) as i64;
+        
+        let mut total = UsdcE6::ZERO;
+        for day in (today - days + 1)..=today {
+            if let Some(bucket) = self.daily_bucket(day) {
+                total = total.checked_add(self.daily_volume_e6[bucket])?;
+            }
+        }
+        Ok(total)
+    }
+    
+    /// 第day天在ring buffer中的下标（不在当前窗口内时返回None）
+    fn daily_bucket(&self, day: i64) -> Option<usize> {
+        let window = Self::VOLUME_WINDOW_DAYS as i64;
+        if day > self.volume_day || day <= self.volume_day - window {
+            return None;
+        }
+        Some(day.rem_euclid(window) as usize)
+    }
+    
+    /// 将成交量计入当天的bucket，必要时先向前滚动窗口（清空过期的bucket）
+    ///
+    /// 早于窗口的成交（乱序到达）只计入累计统计。
+    fn record_daily_volume(&mut self, ts_ms: i64, volume: UsdcE6) -> Result<(), SettlementError> {
+        let day = ts_ms.div_euclid(Self::DAY_MS);
+        let window = Self::VOLUME_WINDOW_DAYS as i64;
+        
+        if day > self.volume_day {
+            let stale_days = (day - self.volume_day).min(window);
+            for stale_day in (day - stale_days + 1)..=day {
+                self.daily_volume_e6[stale_day.rem_euclid(window) as usize] = UsdcE6::ZERO;
+            }
+            self.volume_day = day;
+        }
+        
+        if let Some(bucket) = self.daily_bucket(day) {
+            self.daily_volume_e6[bucket] = self.daily_volume_e6[bucket].checked_add(volume)?;
+        }
+        Ok(())
+    }
+    
+    /// 更新市场统计（只有MARKET_TRADE_SYMBOLS中的市场有计数位置）
+    fn update_market_stats(&mut self, market: &str) -> Result<(), SettlementError> {
+        if let Some(slot) = Self::MARKET_TRADE_SYMBOLS.iter().position(|symbol| *symbol == market) {
+            self.market_trades[slot] = checked_add_u64(self.market_trades[slot], 1)?;
+        }
+        Ok(())
+    }
+}
+
+fn checked_add_u64(a: u64, b: u64) -> Result<u64, SettlementError> {
+    a.checked_add(b).ok_or(SettlementError::ArithmeticOverflow)
+}
+
+// Pod布局必须与SIZE一致
+const _: () = assert!(std::mem::size_of::<UserSettlement>() == UserSettlement::SIZE);
+
+/// v1/v2的UserSettlement布局（累计成交量和手续费为i64），只用于迁移旧版本account
+///
+/// v2为完整的472 bytes；v1 account即其前224 bytes（之后的按日成交量视为0）。
+#[repr(C)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Pod, Zeroable)]
+pub struct LegacyUserSettlement {
+    pub discriminator: u64,
+    pub version: u8,
+    pub bump: u8,
+    pub reserved: [u8; 6],
+    pub wallet: Pubkey,
+    pub total_trades: u64,
+    pub maker_trades: u64,
+    pub taker_trades: u64,
+    pub total_volume_e6: UsdcE6,
+    pub maker_volume_e6: UsdcE6,
+    pub taker_volume_e6: UsdcE6,
+    pub total_fees_e6: UsdcE6,
+    pub maker_fees_e6: UsdcE6,
+    pub taker_fees_e6: UsdcE6,
+    pub first_trade_ts: i64,
+    pub last_trade_ts: i64,
+    pub market_trades: [u64; 3],
+    pub fees_paid_e6: UsdcE6,
+    pub rebates_earned_e6: UsdcE6,
+    pub referrer: Pubkey,
+    pub realized_pnl_e6: UsdcE6,
+    pub reserved_stats: [u64; 1],
+    pub volume_day: i64,
+    pub daily_volume_e6: [UsdcE6; 30],
+}
+
+const _: () = assert!(std::mem::size_of::<LegacyUserSettlement>() == UserSettlement::V2_SIZE);
+
+impl LegacyUserSettlement {
+    /// 读取v1（224 bytes）或v2（472 bytes）account数据
+    pub fn read(data: &[u8]) -> Result<Self, SettlementError> {
+        if data.len() != UserSettlement::V1_SIZE && data.len() != UserSettlement::V2_SIZE {
+            return Err(SettlementError::InvalidSettlementAccount);
+        }
+        
+        // account数据不保证按Self对齐，复制到对齐的值中
+        let mut legacy = Self::zeroed();
+        bytemuck::bytes_of_mut(&mut legacy)[..data.len()].copy_from_slice(data);
+        Ok(legacy)
+    }
+    
+    /// 转换为当前版本（资金费统计为0）
+    pub fn migrate(&self) -> UserSettlement {
+        UserSettlement {
+            discriminator: self.discriminator,
+            version: UserSettlement::VERSION,
+            bump: self.bump,
+            reserved: self.reserved,
+            wallet: self.wallet,
+            total_trades: self.total_trades,
+            maker_trades: self.maker_trades,
+            taker_trades: self.taker_trades,
+            total_volume_e6: self.total_volume_e6.into(),
+            maker_volume_e6: self.maker_volume_e6.into(),
+            taker_volume_e6: self.taker_volume_e6.into(),
+            total_fees_e6: self.total_fees_e6.into(),
+            maker_fees_e6: self.maker_fees_e6.into(),
+            taker_fees_e6: self.taker_fees_e6.into(),
+            first_trade_ts: self.first_trade_ts,
+            last_trade_ts: self.last_trade_ts,
+            market_trades: self.market_trades,
+            fees_paid_e6: self.fees_paid_e6.into(),
+            rebates_earned_e6: self.rebates_earned_e6.into(),
+            referrer: self.referrer,
+            realized_pnl_e6: self.realized_pnl_e6,
+            reserved_stats: self.reserved_stats,
+            volume_day: self.volume_day,
+            daily_volume_e6: self.daily_volume_e6,
+            funding_paid_e6: UsdcE6::ZERO,
+            funding_received_e6: UsdcE6::ZERO,
+        }
+    }
+}
+
+/// 用户在单个市场的统计账户（每个用户每个市场一个）
+/// PDA Seeds: [b"user_market", user_wallet.as_ref(), market_id.to_le_bytes()]
+///
+/// 在用户首次于该市场成交时由 `RecordSettlement` 创建（relayer支付租金），
+/// 与UserSettlement相同，使用 `#[repr(C)]` Pod布局直接在account数据上读写。
+#[repr(C)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, Pod, Zeroable)]
+pub struct UserMarketStats {
+    /// 账户类型标识符 "USRMKTST" = 0x5553524d_4b545354
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 市场ID（Market.market_id）
+    pub market_id: u16,
+    
+    /// 预留字段（对齐）
+    pub reserved: [u8; 4],
+    
+    /// 用户钱包地址
+    pub wallet: Pubkey,
+    
+    // === 交易次数统计 ===
+    pub total_trades: u64,             // 总交易次数
+    pub maker_trades: u64,             // 作为maker的次数
+    pub taker_trades: u64,             // 作为taker的次数
+    
+    // === 交易量统计（USDC, e6格式）===
+    pub total_volume_e6: UsdcE6,       // 总交易量
+    pub maker_volume_e6: UsdcE6,       // 作为maker的交易量
+    pub taker_volume_e6: UsdcE6,       // 作为taker的交易量
+    
+    // === 手续费统计（USDC, e6格式）===
+    pub total_fees_e6: UsdcE6,         // 总手续费（净支出，正数=支付）
+    pub maker_fees_e6: UsdcE6,         // maker手续费（负数=收入）
+    pub taker_fees_e6: UsdcE6,         // taker手续费（正数=支付）
+    
+    // === 时间戳 ===
+    pub first_trade_ts: i64,           // 该市场首次交易时间（毫秒）
+    pub last_trade_ts: i64,            // 该市场最后交易时间（毫秒）
+    
+    // === 持仓（占用原预留字段，之前的成交不计入）===
+    pub position_qty_e6: QtyE6,        // 持仓数量（正数 = 多头，负数 = 空头）
+    pub avg_entry_price_e6: PriceE6,   // 持仓均价（无持仓时为0）
+    pub realized_pnl_e6: UsdcE6,       // 累计已实现盈亏（不含手续费）
+    
+    // === 预留扩展字段 ===
+    pub reserved_stats: [u64; 5],      // 未来扩展用
+}
+
+impl UserMarketStats {
+    /// 账户类型标识符 "USRMKTST"
+    pub const DISCRIMINATOR: u64 = 0x5553524d_4b545354;
+    
+    /// 当前版本
+    pub const VERSION: u8 = 1;
+    
+    /// 固定大小（bytes）
+    /// 8 + 1 + 1 + 2 + 4 + 32 + 8*3 + 8*3 + 8*3 + 8*2 + 8*3 + 8*5 = 200 bytes
+    pub const SIZE: usize = 200;
+    
+    /// 以account数据构造只读视图（zero-copy）
+    pub fn load(data: &[u8]) -> Result<&Self, SettlementError> {
+        let bytes = data.get(..Self::SIZE).ok_or(SettlementError::InvalidSettlementAccount)?;
+        bytemuck::try_from_bytes(bytes).map_err(|_| SettlementError::InvalidSettlementAccount)
+    }
+    
+    /// 以account数据构造可写视图（zero-copy），修改直接写入account
+    pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, SettlementError> {
+        let bytes = data.get_mut(..Self::SIZE).ok_or(SettlementError::InvalidSettlementAccount)?;
+        bytemuck::try_from_bytes_mut(bytes).map_err(|_| SettlementError::InvalidSettlementAccount)
+    }
+    
+    /// 创建新的UserMarketStats（初始状态，first_trade_ts在首次更新时设置）
+    pub fn new(wallet: Pubkey, market_id: u16, bump: u8) -> Self {
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            market_id,
+            reserved: [0; 4],
+            wallet,
+            total_trades: 0,
+            maker_trades: 0,
+            taker_trades: 0,
+            total_volume_e6: UsdcE6::ZERO,
+            maker_volume_e6: UsdcE6::ZERO,
+            taker_volume_e6: UsdcE6::ZERO,
+            total_fees_e6: UsdcE6::ZERO,
+            maker_fees_e6: UsdcE6::ZERO,
+            taker_fees_e6: UsdcE6::ZERO,
+            first_trade_ts: 0,
+            last_trade_ts: 0,
+            position_qty_e6: QtyE6::ZERO,
+            avg_entry_price_e6: PriceE6::ZERO,
+            realized_pnl_e6: UsdcE6::ZERO,
+            reserved_stats: [0; 5],
+        }
+    }
+    
+    /// 更新统计（作为taker）
+    pub fn update_as_taker(&mut self, trade: &CompleteTrade) -> Result<(), SettlementError> {
+        let volume = trade.notional_e6;
+        self.record_trade_ts(trade.ts_ms);
+        
+        self.total_trades = checked_add_u64(self.total_trades, 1)?;
+        self.taker_trades = checked_add_u64(self.taker_trades, 1)?;
+        
+        self.total_volume_e6 = self.total_volume_e6.checked_add(volume)?;
+        self.taker_volume_e6 = self.taker_volume_e6.checked_add(volume)?;
+        
+        self.total_fees_e6 = self.total_fees_e6.checked_add(trade.taker_fee_e6)?;
+        self.taker_fees_e6 = self.taker_fees_e6.checked_add(trade.taker_fee_e6)?;
+        Ok(())
+    }
+    
+    /// 更新统计（作为maker）
+    pub fn update_as_maker(&mut self, trade: &CompleteTrade) -> Result<(), SettlementError> {
+        let volume = trade.notional_e6;
+        self.record_trade_ts(trade.ts_ms);
+        
+        self.total_trades = checked_add_u64(self.total_trades, 1)?;
+        self.maker_trades = checked_add_u64(self.maker_trades, 1)?;
+        
+        self.total_volume_e6 = self.total_volume_e6.checked_add(volume)?;
+        self.maker_volume_e6 = self.maker_volume_e6.checked_add(volume)?;
+        
+        self.total_fees_e6 = self.total_fees_e6.checked_add(trade.maker_fee_e6)?;
+        self.maker_fees_e6 = self.maker_fees_e6.checked_add(trade.maker_fee_e6)?;
+        Ok(())
+    }
+    
+    /// 按成交更新持仓，返回本次成交的已实现盈亏
+    ///
+    /// 加仓时按数量加权更新均价（四舍五入）；减仓时按 (成交价 - 均价) * 平仓数量 计算盈亏
+    /// （按市场参数换算成USDC，与成交额相同的取整）；反向开仓的部分以成交价为新均价。
+    pub fn apply_fill(
+        &mut self,
+        side: Side,
+        price: PriceE6,
+        qty: QtyE6,
+        params: &MarketParams,
+    ) -> Result<UsdcE6, SettlementError> {
+        let delta = match side {
+            Side::Buy => qty,
+            Side::Sell => qty.checked_neg()?,
+        };
+        let position = self.position_qty_e6;
+        let new_position = position.checked_add(delta)?;
+        
+        // 开仓或加仓
+        if position == QtyE6::ZERO || position.is_negative() == delta.is_negative() {
+            let cost = (self.avg_entry_price_e6.0 as i128 * position.0.unsigned_abs() as i128)
+                .checked_add(price.0 as i128 * qty.0 as i128)
+                .ok_or(SettlementError::ArithmeticOverflow)?;
+            self.avg_entry_price_e6 = PriceE6(div_round(
+                cost,
+                new_position.0.unsigned_abs() as i128,
+                Rounding::HalfAwayFromZero,
+            )?);
+            self.position_qty_e6 = new_position;
+            return Ok(UsdcE6::ZERO);
+        }
+        
+        // 减仓、平仓或反向开仓
+        let closed = QtyE6(position.0.abs().min(qty.0));
+        let price_move = if position.is_positive() {
+            price.checked_sub(self.avg_entry_price_e6)?
+        } else {
+            self.avg_entry_price_e6.checked_sub(price)?
+        };
+        let pnl = params.notional(price_move, closed)?;
+        
+        self.realized_pnl_e6 = self.realized_pnl_e6.checked_add(pnl)?;
+        self.position_qty_e6 = new_position;
+        if new_position == QtyE6::ZERO {
+            self.avg_entry_price_e6 = PriceE6::ZERO;
+        } else if new_position.is_negative() != position.is_negative() {
+            self.avg_entry_price_e6 = price;
+        }
+        
+        Ok(pnl)
+    }
+    
+    /// 更新首次/最后交易时间
+    fn record_trade_ts(&mut self, ts_ms: i64) {
+        if self.total_trades == 0 {
+            self.first_trade_ts = ts_ms;
+        }
+        self.last_trade_ts = ts_ms;
+    }
+}
+
+const _: () = assert!(std::mem::size_of::<UserMarketStats>() == UserMarketStats::SIZE);
+
+/// 推荐人统计账户（每个推荐人一个）
+/// PDA Seeds: [b"referrer_stats", referrer_wallet.as_ref()]
+///
+/// 由第一个指定该推荐人的 `SetReferrer` 创建，`RecordSettlement` 按
+/// `ProgramConfig.referral_share_bp` 累加被推荐用户taker手续费的分成。
+/// 与UserSettlement相同，使用 `#[repr(C)]` Pod布局直接在account数据上读写。
+#[repr(C)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, Pod, Zeroable)]
+pub struct ReferrerStats {
+    /// 账户类型标识符 "REFSTATS" = 0x52454653_54415453
+    pub discriminator: u64,
+    
+    /// 数据版本
+    pub version: u8,
+    
+    /// PDA bump seed
+    pub bump: u8,
+    
+    /// 预留字段（对齐）
+    pub reserved: [u8; 6],
+    
+    /// 推荐人钱包地址
+    pub referrer: Pubkey,
+    
+    // === 推荐统计 ===
+    pub referred_users: u64,           // 指定该推荐人的用户数
+    pub referred_trades: u64,          // 被推荐用户作为taker的成交笔数
+    pub referred_volume_e6: UsdcE6,    // 被推荐用户作为taker的成交额
+    pub referred_fees_e6: UsdcE6,      // 被推荐用户支付的taker手续费
+    pub rewards_e6: UsdcE6,            // 累计分成（referred_fees_e6 * share_bp / 10_000，逐笔向零截断）
+    pub last_reward_ts: i64,           // 最近一笔被推荐成交的时间（毫秒）
+    
+    // === 预留扩展字段 ===
+    pub reserved_stats: [u64; 8],      // 未来扩展用
+}
+
+impl ReferrerStats {
+    /// 账户类型标识符 "REFSTATS"
+    pub const DISCRIMINATOR: u64 = 0x52454653_54415453;
+    
+    /// 当前版本
+    pub const VERSION: u8 = 1;
+    
+    /// 分成比例的单位：1/10_000（bp）
+    pub const SHARE_DENOMINATOR: i64 = 10_000;
+    
+    /// 固定大小（bytes）
+    /// 8 + 1 + 1 + 6 + 32 + 8*2 + 8*3 + 8 + 8*8 = 160 bytes
+    pub const SIZE: usize = 160;
+    
+    /// 以account数据构造只读视图（zero-copy）
+    pub fn load(data: &[u8]) -> Result<&Self, SettlementError> {
+        let bytes = data.get(..Self::SIZE).ok_or(SettlementError::InvalidSettlementAccount)?;
+        bytemuck::try_from_bytes(bytes).map_err(|_| SettlementError::InvalidSettlementAccount)
+    }
+    
+    /// 以account数据构造可写视图（zero-copy），修改直接写入account
+    pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, SettlementError> {
+        let bytes = data.get_mut(..Self::SIZE).ok_or(SettlementError::InvalidSettlementAccount)?;
+        bytemuck::try_from_bytes_mut(bytes).map_err(|_| SettlementError::InvalidSettlementAccount)
+    }
+    
+    /// 创建新的ReferrerStats（初始状态）
+    pub fn new(referrer: Pubkey, bump: u8) -> Self {
+        Self {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            reserved: [0; 6],
+            referrer,
+            referred_users: 0,
+            referred_trades: 0,
+            referred_volume_e6: UsdcE6::ZERO,
+            referred_fees_e6: UsdcE6::ZERO,
+            rewards_e6: UsdcE6::ZERO,
+            last_reward_ts: 0,
+            reserved_stats: [0; 8],
+        }
+    }
+    
+    /// 累加一笔被推荐用户作为taker的成交，返回本笔分成
+    pub fn record_referred_trade(
+        &mut self,
+        trade: &CompleteTrade,
+        share_bp: u16,
+    ) -> Result<UsdcE6, SettlementError> {
+        let reward = trade.taker_fee_e6.checked_mul_div(
+            share_bp as i64,
+            Self::SHARE_DENOMINATOR,
+            Rounding::TowardZero,
+        )?;
+        
+        self.referred_trades = checked_add_u64(self.referred_trades, 1)?;
+        self.referred_volume_e6 = self.referred_volume_e6.checked_add(trade.notional_e6)?;
+        self.referred_fees_e6 = self.referred_fees_e6.checked_add(trade.taker_fee_e6)?;
+        self.rewards_e6 = self.rewards_e6.checked_add(reward)?;
+        self.last_reward_ts = trade.ts_ms;
+        
+        Ok(reward)
+    }
+}
+
+const _: () = assert!(std::mem::size_of::<ReferrerStats>() == ReferrerStats::SIZE);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn populated_user() -> UserSettlement {
+        let mut user = UserSettlement::new(Pubkey::new_unique(), 254, 1762897603000);
+        user.total_trades = 3;
+        user.maker_trades = 1;
+        user.taker_trades = 2;
+        user.total_volume_e6 = WideUsdcE6::new(315945000);
+        user.maker_fees_e6 = WideUsdcE6::new(-15797);
+        user.taker_fees_e6 = WideUsdcE6::new(94782);
+        user.last_trade_ts = 1762897604000;
+        user.market_trades[0] = 3;
+        user.referrer = Pubkey::new_unique();
+        user.realized_pnl_e6 = UsdcE6(-1_500_000);
+        user.reserved_stats[0] = u64::MAX;
+        user
+    }
+    
+    #[test]
+    fn test_program_config_max_size() {
+        let relayers = (0..ProgramConfig::MAX_RELAYERS)
+            .map(|_| RelayerInfo::new(Pubkey::new_unique(), "x".repeat(RelayerInfo::MAX_LABEL_LEN), 0))
+            .collect();
+        let mut config = ProgramConfig::new(Pubkey::new_unique(), 255, relayers);
+        config.pending_admin = Some(Pubkey::new_unique());
+        config.funding_tolerance_e6 = u64::MAX;
+        
+        assert_eq!(config.try_to_vec().unwrap().len(), ProgramConfig::SIZE);
+    }
+    
+    #[test]
+    fn test_stats_tracking_reuses_reserved_bytes() {
+        let mut config = ProgramConfig::new(Pubkey::new_unique(), 255, vec![]);
+        assert_eq!(config.stats_tracking, StatsTracking::ALL);
+        
+        // 原预留字段位于offset 14，旧config中为全0，解码为全部更新
+        let encoded = config.try_to_vec().unwrap();
+        assert_eq!(&encoded[14..16], &[0, 0]);
+        
+        config.stats_tracking = StatsTracking::MINIMAL;
+        let encoded = config.try_to_vec().unwrap();
+        assert_eq!(&encoded[14..16], &[1, 1]);
+        assert_eq!(ProgramConfig::try_from_slice(&encoded).unwrap().stats_tracking, StatsTracking::MINIMAL);
+    }
+    
+    #[test]
+    fn test_user_settlement_pod_matches_borsh() {
+        let user = populated_user();
+        let encoded = user.try_to_vec().unwrap();
+        
+        assert_eq!(encoded.len(), UserSettlement::SIZE);
+        assert_eq!(bytemuck::bytes_of(&user), &encoded[..]);
+    }
+    
+    #[test]
+    fn test_user_settlement_load_account() {
+        let user = populated_user();
+        
+        // 8字节对齐的buffer，与链上account数据一致
+        let mut buffer = [0u64; UserSettlement::SIZE / 8];
+        let data: &mut [u8] = bytemuck::cast_slice_mut(&mut buffer);
+        user.serialize(&mut &mut data[..]).unwrap();
+        
+        let loaded = UserSettlement::load_mut(data).unwrap();
+        assert_eq!(loaded.wallet, user.wallet);
+        assert_eq!(loaded.maker_fees_e6, WideUsdcE6::new(-15797));
+        loaded.taker_trades += 1;
+        
+        let decoded = UserSettlement::try_from_slice(data).unwrap();
+        assert_eq!(decoded.taker_trades, 3);
+        assert_eq!(decoded.reserved_stats, user.reserved_stats);
+        
+        assert!(UserSettlement::load(&data[..UserSettlement::SIZE - 1]).is_err());
+        
+        // 旧版本account需要先扩容迁移
+        assert!(UserSettlement::load(&data[..UserSettlement::V1_SIZE]).is_err());
+        assert!(UserSettlement::load(&data[..UserSettlement::V2_SIZE]).is_err());
+    }
+    
+    #[test]
+    fn test_legacy_user_settlement_migration() {
+        let mut legacy = LegacyUserSettlement::zeroed();
+        legacy.discriminator = UserSettlement::DISCRIMINATOR;
+        legacy.version = 2;
+        legacy.bump = 254;
+        legacy.wallet = Pubkey::new_unique();
+        legacy.total_trades = 3;
+        legacy.total_volume_e6 = UsdcE6(i64::MAX);
+        legacy.maker_fees_e6 = UsdcE6(-15797);
+        legacy.last_trade_ts = 1762897604000;
+        legacy.market_trades = [1, 2, 0];
+        legacy.referrer = Pubkey::new_unique();
+        legacy.volume_day = 20405;
+        legacy.daily_volume_e6[5] = UsdcE6(105315000);
+        let data = bytemuck::bytes_of(&legacy);
+        assert_eq!(data, &legacy.try_to_vec().unwrap()[..]);
+        
+        let user = LegacyUserSettlement::read(data).unwrap().migrate();
+        assert_eq!(user.version, UserSettlement::VERSION);
+        assert_eq!((user.bump, user.wallet, user.referrer), (254, legacy.wallet, legacy.referrer));
+        assert_eq!(user.total_volume_e6, WideUsdcE6::from(UsdcE6(i64::MAX)));
+        assert_eq!(user.maker_fees_e6, WideUsdcE6::new(-15797));
+        assert_eq!((user.last_trade_ts, user.market_trades), (1762897604000, [1, 2, 0]));
+        assert_eq!(user.daily_volume_e6[5], UsdcE6(105315000));
+        assert_eq!(user.funding_paid_e6, UsdcE6::ZERO);
+        
+        // v1只有前224 bytes，按日成交量为0
+        let user = LegacyUserSettlement::read(&data[..UserSettlement::V1_SIZE]).unwrap().migrate();
+        assert_eq!(user.total_trades, 3);
+        assert_eq!((user.volume_day, user.daily_volume_e6[5]), (0, UsdcE6::ZERO));
+        
+        assert!(LegacyUserSettlement::read(&data[..UserSettlement::V1_SIZE - 8]).is_err());
+    }
+    
+    #[test]
+    fn test_trailing_volume_window() {
+        const DAY: i64 = UserSettlement::DAY_MS;
+        let mut user = UserSettlement::new(Pubkey::new_unique(), 255, 0);
+        let mut trade = test_trade();
+        let day0 = trade.ts_ms;
+        
+        // 第0天2笔，第1天1笔
+        user.update_as_taker(&trade).unwrap();
+        user.update_as_maker(&trade).unwrap();
+        trade.ts_ms = day0 + DAY;
+        user.update_as_taker(&trade).unwrap();
+        
+        let volume = trade.notional_e6.0;
+        assert_eq!(user.trailing_volume(day0 + DAY, 1), Ok(UsdcE6(volume)));
+        assert_eq!(user.trailing_volume(day0 + DAY, 30), Ok(UsdcE6(3 * volume)));
+        assert_eq!(user.trailing_volume(day0 + DAY, 365), Ok(UsdcE6(3 * volume)));
+        
+        // 29天后第0天滑出30天窗口
+        assert_eq!(user.trailing_volume(day0 + 30 * DAY, 30), Ok(UsdcE6(volume)));
+        assert_eq!(user.trailing_volume(day0 + 31 * DAY, 30), Ok(UsdcE6::ZERO));
+        
+        // 窗口向前滚动时清空过期bucket
+        trade.ts_ms = day0 + 30 * DAY;
+        user.update_as_taker(&trade).unwrap();
+        assert_eq!(user.trailing_volume(trade.ts_ms, 30), Ok(UsdcE6(2 * volume)));
+        
+        // 窗口内的乱序成交计入对应的天，早于窗口的不计入
+        trade.ts_ms = day0 + 5 * DAY;
+        user.update_as_maker(&trade).unwrap();
+        trade.ts_ms = day0;
+        user.update_as_maker(&trade).unwrap();
+        assert_eq!(user.trailing_volume(day0 + 30 * DAY, 30), Ok(UsdcE6(3 * volume)));
+        assert_eq!(user.total_volume_e6, WideUsdcE6::from(UsdcE6(6 * volume)));
+    }
+    
+    fn test_trade() -> CompleteTrade {
+        CompleteTrade {
+            id: "trade_1".to_string(),
+            market: "BTC-PERP".to_string(),
+            price_e6: PriceE6(105315000000),
+            qty_e6: QtyE6(1000),
+            notional_e6: UsdcE6(105315000),
+            taker_side: Side::Buy,
+            ts_ms: 1762897603000,
+            engine_seq: 1,
+            taker_order_id: "ord_1".to_string(),
+            maker_order_id: "ord_2".to_string(),
+            taker_account_id: "account_1".to_string(),
+            maker_account_id: "account_2".to_string(),
+            taker_wallet: Pubkey::new_unique(),
+            maker_wallet: Pubkey::new_unique(),
+            taker_leverage: 20,
+            maker_leverage: 20,
+            taker_fee_e6: UsdcE6(47391),
+            maker_fee_e6: UsdcE6(15797),
+            fee_rate_taker_e5: 45,
+            fee_rate_maker_e5: 15,
+        }
+    }
+    
+    #[test]
+    fn test_update_overflow_returns_error() {
+        let trade = test_trade();
+        
+        let mut user = populated_user();
+        user.update_as_taker(&trade).unwrap();
+        assert_eq!(user.taker_trades, 3);
+        assert_eq!(user.market_trades, [4, 0, 0]);
+        
+        // market_trades之外的市场只更新总计
+        let mut other = trade.clone();
+        other.market = "kPEPE-PERP".to_string();
+        user.update_as_taker(&other).unwrap();
+        assert_eq!(user.taker_trades, 4);
+        assert_eq!(user.market_trades, [4, 0, 0]);
+        
+        // 累计成交量为128位，超出i64后继续累加
+        user.taker_volume_e6 = WideUsdcE6::from(UsdcE6(i64::MAX));
+        user.update_as_taker(&trade).unwrap();
+        assert_eq!(user.taker_volume_e6, WideUsdcE6::new(i64::MAX as i128 + 105315000));
+        
+        user.taker_trades = u64::MAX;
+        assert_eq!(user.update_as_taker(&trade), Err(SettlementError::ArithmeticOverflow));
+    }
+    
+    #[test]
+    fn test_market_trades_by_symbol() {
+        let mut user = UserSettlement::new(Pubkey::new_unique(), 254, 1762897603000);
+        
+        // 按symbol计数，与market_id无关
+        for (symbol, count) in [("SOL-PERP", 2), ("ETH-PERP", 1), ("kPEPE-PERP", 3)] {
+            let mut trade = test_trade();
+            trade.market = symbol.to_string();
+            for _ in 0..count {
+                user.update_as_taker(&trade).unwrap();
+            }
+        }
+        
+        assert_eq!(user.market_trades, [0, 1, 2]);
+        assert_eq!(user.total_trades, 6);
+    }
+    
+    #[test]
+    fn test_maker_rebate_accounting() {
+        let trade = test_trade();
+        
+        // populated_user的历史手续费早于fees_paid_e6，不计入
+        let mut user = populated_user();
+        user.update_as_taker(&trade).unwrap();
+        assert_eq!(user.fees_paid_e6, WideUsdcE6::new(47391));
+        assert_eq!(user.rebates_earned_e6, WideUsdcE6::ZERO);
+        
+        // 返佣单独累计，fees_paid_e6只含实际支付的手续费
+        let mut rebate = trade.clone();
+        rebate.maker_fee_e6 = UsdcE6(-5266);
+        user.update_as_maker(&rebate).unwrap();
+        user.update_as_maker(&trade).unwrap();
+        assert_eq!(user.rebates_earned_e6, WideUsdcE6::new(5266));
+        assert_eq!(user.fees_paid_e6, WideUsdcE6::new(47391 + 15797));
+        
+        // maker_fees_e6仍为净额
+        assert_eq!(user.maker_fees_e6, WideUsdcE6::new(-15797 - 5266 + 15797));
+        
+        user.rebates_earned_e6 = WideUsdcE6::new(i128::MAX);
+        assert_eq!(user.update_as_maker(&rebate), Err(SettlementError::ArithmeticOverflow));
+    }
+    
+    #[test]
+    fn test_record_funding() {
+        let mut user = populated_user();
+        
+        // 资金费按方向分别累计
+        user.record_funding(UsdcE6(1200)).unwrap();
+        user.record_funding(UsdcE6(-300)).unwrap();
+        user.record_funding(UsdcE6(-200)).unwrap();
+        assert_eq!((user.funding_paid_e6, user.funding_received_e6), (UsdcE6(1200), UsdcE6(500)));
+        
+        // 交易统计不受影响
+        assert_eq!(user.total_trades, 3);
+        assert_eq!(user.total_volume_e6, WideUsdcE6::new(315945000));
+        
+        assert_eq!(user.record_funding(UsdcE6(i64::MIN)), Err(SettlementError::ArithmeticOverflow));
+        user.funding_paid_e6 = UsdcE6(i64::MAX);
+        assert_eq!(user.record_funding(UsdcE6(1)), Err(SettlementError::ArithmeticOverflow));
+    }
+    
+    #[test]
+    fn test_user_market_stats_pod_matches_borsh() {
+        let mut stats = UserMarketStats::new(Pubkey::new_unique(), 2, 253);
+        stats.first_trade_ts = 1762897603000;
+        stats.maker_fees_e6 = UsdcE6(-15797);
+        stats.position_qty_e6 = QtyE6(-2_000);
+        stats.reserved_stats[4] = u64::MAX;
+        
+        let encoded = stats.try_to_vec().unwrap();
+        assert_eq!(encoded.len(), UserMarketStats::SIZE);
+        assert_eq!(bytemuck::bytes_of(&stats), &encoded[..]);
+    }
+    
+    #[test]
+    fn test_position_and_realized_pnl() {
+        const USDC: i64 = 1_000_000;
+        let mut stats = UserMarketStats::new(Pubkey::new_unique(), 0, 253);
+        let mut fill = |side, price: i64, qty: i64| {
+            stats.apply_fill(side, PriceE6(price * USDC), QtyE6(qty * USDC), &MarketParams::STANDARD)
+                .unwrap();
+            (stats.realized_pnl_e6, stats.position_qty_e6, stats.avg_entry_price_e6)
+        };
+        
+        // 加仓：均价按数量加权
+        assert_eq!(fill(Side::Buy, 100, 2), (UsdcE6::ZERO, QtyE6(2 * USDC), PriceE6(100 * USDC)));
+        assert_eq!(fill(Side::Buy, 110, 2), (UsdcE6::ZERO, QtyE6(4 * USDC), PriceE6(105 * USDC)));
+        // 减仓：(115 - 105) * 1，均价不变
+        assert_eq!(fill(Side::Sell, 115, 1), (UsdcE6(10 * USDC), QtyE6(3 * USDC), PriceE6(105 * USDC)));
+        // 反向：平多3个 (100 - 105) * 3，再以100开空2个
+        assert_eq!(fill(Side::Sell, 100, 5), (UsdcE6(-5 * USDC), QtyE6(-2 * USDC), PriceE6(100 * USDC)));
+        // 平空：(100 - 90) * 2
+        assert_eq!(fill(Side::Buy, 90, 2), (UsdcE6(15 * USDC), QtyE6::ZERO, PriceE6::ZERO));
+        
+        // 均价四舍五入：(100 * 1 + 101 * 2) / 3 = 100.666667
+        fill(Side::Sell, 100, 1);
+        assert_eq!(fill(Side::Sell, 101, 2).2, PriceE6(100_666_667));
+    }
+    
+    #[test]
+    fn test_referrer_stats_rewards() {
+        let mut stats = ReferrerStats::new(Pubkey::new_unique(), 252);
+        assert_eq!(stats.try_to_vec().unwrap(), bytemuck::bytes_of(&stats));
+        
+        // 47391 * 20% = 9478.2，向零截断
+        let trade = test_trade();
+        assert_eq!(stats.record_referred_trade(&trade, 2_000), Ok(UsdcE6(9478)));
+        assert_eq!(stats.record_referred_trade(&trade, 0), Ok(UsdcE6::ZERO));
+        assert_eq!(stats.referred_trades, 2);
+        assert_eq!(stats.referred_fees_e6, UsdcE6(2 * 47391));
+        assert_eq!(stats.rewards_e6, UsdcE6(9478));
+        assert_eq!(stats.last_reward_ts, trade.ts_ms);
+        
+        let mut user = populated_user();
+        assert!(user.referrer().is_some());
+        user.referrer = Pubkey::default();
+        assert_eq!(user.referrer(), None);
+    }
+    
+    #[test]
+    fn test_market_stats_period_high_low() {
+        let mut stats = MarketStats::new(0, 255);
+        let mut trade = test_trade();
+        stats.update(&trade).unwrap();
+        
+        trade.price_e6 = PriceE6(106000000000);
+        trade.engine_seq = 2;
+        stats.update(&trade).unwrap();
+        trade.price_e6 = PriceE6(104000000000);
+        trade.engine_seq = 3;
+        stats.update(&trade).unwrap();
+        
+        assert_eq!(stats.total_trades, 3);
+        assert_eq!(stats.total_volume_e6, 3 * 105315000);
+        assert_eq!(stats.total_fees_e6, 3 * (47391 + 15797));
+        assert_eq!(stats.last_price_e6, PriceE6(104000000000));
+        assert_eq!(stats.last_engine_seq, 3);
+        assert_eq!(stats.period_high_e6, PriceE6(106000000000));
+        assert_eq!(stats.period_low_e6, PriceE6(104000000000));
+        assert_eq!(stats.period_start_ts % MarketStats::PERIOD_MS, 0);
+        
+        // 下一个UTC日重置高/低价
+        trade.ts_ms += MarketStats::PERIOD_MS;
+        trade.price_e6 = PriceE6(105000000000);
+        stats.update(&trade).unwrap();
+        assert_eq!(stats.period_high_e6, PriceE6(105000000000));
+        assert_eq!(stats.period_low_e6, PriceE6(105000000000));
+        
+        // 早于当前周期的成交只计入累计统计
+        trade.ts_ms -= MarketStats::PERIOD_MS;
+        trade.price_e6 = PriceE6(90000000000);
+        stats.update(&trade).unwrap();
+        assert_eq!(stats.period_low_e6, PriceE6(105000000000));
+        assert_eq!(stats.total_trades, 5);
+        
+        assert_eq!(stats.try_to_vec().unwrap().len(), MarketStats::SIZE);
+    }
+    
+    #[test]
+    fn test_fee_schedule_tiers() {
+        let tiers = vec![
+            FeeTier { min_volume_e6: UsdcE6::ZERO, taker_rate_e5: 45, maker_rate_e5: 15 },
+            FeeTier { min_volume_e6: UsdcE6(1_000_000_000_000), taker_rate_e5: 30, maker_rate_e5: -5 },
+        ];
+        assert!(FeeSchedule::validate_tiers(&tiers).is_ok());
+        
+        let schedule = FeeSchedule {
+            discriminator: FeeSchedule::DISCRIMINATOR,
+            version: FeeSchedule::VERSION,
+            bump: 255,
+            reserved: [0; 6],
+            tolerance_e6: 1,
+            updated_at: 0,
+            tiers: tiers.clone(),
+        };
+        assert_eq!(schedule.tier_for(UsdcE6(999_999_999_999)), Some(&tiers[0]));
+        assert_eq!(schedule.tier_for(UsdcE6(1_000_000_000_000)), Some(&tiers[1]));
+        
+        let trade = test_trade();
+        assert!(schedule.validate_taker_fee(&trade, UsdcE6::ZERO).is_ok());
+        assert!(schedule.validate_maker_fee(&trade, UsdcE6::ZERO).is_ok());
+        assert_eq!(schedule.validate_taker_fee(&trade, UsdcE6(1_000_000_000_000)),
+            Err(SettlementError::FeeMismatch));
+        
+        // 返佣：-5 → -0.00005 * notional
+        assert_eq!(FeeSchedule::fee_for(trade.notional_e6, -5), Ok(UsdcE6(-5266)));
+        assert!(schedule.try_to_vec().unwrap().len() <= FeeSchedule::SIZE);
+        
+        let unordered = vec![tiers[0], tiers[1], tiers[1]];
+        assert_eq!(FeeSchedule::validate_tiers(&unordered), Err(SettlementError::InvalidFeeSchedule));
+        let net_negative = vec![FeeTier { maker_rate_e5: -46, ..tiers[0] }];
+        assert_eq!(FeeSchedule::validate_tiers(&net_negative), Err(SettlementError::InvalidFeeSchedule));
+        assert_eq!(FeeSchedule::validate_tiers(&[]), Err(SettlementError::InvalidFeeSchedule));
+    }
+    
+    #[test]
+    fn test_market_notional() {
+        let price = PriceE6(105315000000);
+        let qty = QtyE6(1000);
+        assert_eq!(MarketParams::STANDARD.notional(price, qty), UsdcE6::notional(price, qty));
+        
+        // 数量3位小数：2.5 ETH @ 3000 USDC
+        let eth = MarketParams { base_decimals: 3, ..MarketParams::STANDARD };
+        assert_eq!(eth.notional(PriceE6(3_000_000_000), QtyE6(2_500)), Ok(UsdcE6(7_500_000_000)));
+        
+        // 每张合约1000个基础资产：10张 @ 0.000012 USDC
+        let kpepe = MarketParams { contract_multiplier_e6: 1_000_000_000, ..MarketParams::STANDARD };
+        assert_eq!(kpepe.notional(PriceE6(12), QtyE6(10_000_000)), Ok(UsdcE6(120_000)));
+        
+        // 价格8位小数
+        let quote8 = MarketParams { quote_decimals: 8, ..MarketParams::STANDARD };
+        assert_eq!(quote8.notional(PriceE6(300_000_000_000), QtyE6(2_500_000)), Ok(UsdcE6(7_500_000_000)));
+        
+        assert!(MarketParams { contract_multiplier_e6: 0, ..MarketParams::STANDARD }.validate().is_err());
+        assert!(MarketParams { base_decimals: 19, ..MarketParams::STANDARD }.validate().is_err());
+        assert!(MarketParams::STANDARD.validate().is_ok());
+    }
+    
+    #[test]
+    fn test_market_notional_overflow() {
+        // price * qty / 1e6 超出i64
+        assert_eq!(
+            MarketParams::STANDARD.notional(PriceE6(i64::MAX), QtyE6(2_000_000)),
+            Err(SettlementError::ArithmeticOverflow)
+        );
+    }
+}
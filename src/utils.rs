@@ -1,14 +1,20 @@
 //! Settlement Program Utility Functions
 
-use solana_program::entrypoint::ProgramResult;
+use borsh::BorshSerialize;
+use sha2::{Digest, Sha256};
+use solana_program::{entrypoint::ProgramResult, pubkey::Pubkey};
 
 use crate::{
+    amount::UsdcE6,
     error::SettlementError,
-    state::CompleteTrade,
+    state::{BatchId, CompleteTrade, Market},
 };
 
 /// 验证trades数据的基本有效性
-pub fn validate_settlement_data(trades: &[CompleteTrade]) -> ProgramResult {
+///
+/// markets为batch涉及的已登记市场，notional按对应市场参数校验，
+/// price/qty/杠杆需满足市场的tick/lot/最大杠杆限制。
+pub fn validate_settlement_data(trades: &[CompleteTrade], markets: &[Market]) -> ProgramResult {
     // 1. 验证trades非空
     if trades.is_empty() {
         return Err(SettlementError::EmptyTrades.into());
@@ -17,18 +23,32 @@ pub fn validate_settlement_data(trades: &[CompleteTrade]) -> ProgramResult {
     // 2. 验证每个trade的基本有效性
     for trade in trades {
         // 验证价格和数量为正
-        if trade.price_e6 <= 0 || trade.qty_e6 <= 0 {
+        if !trade.price_e6.is_positive() || !trade.qty_e6.is_positive() {
             return Err(SettlementError::InvalidTrade.into());
         }
         
-        // 验证notional计算正确
-        let expected_notional = (trade.price_e6 as i128 * trade.qty_e6 as i128 / 1_000_000) as i64;
+        // 验证notional按市场参数计算正确
+        let market = markets.iter()
+            .find(|m| m.symbol == trade.market)
+            .ok_or(SettlementError::UnknownMarket)?;
+        let expected_notional = market.notional(trade.price_e6, trade.qty_e6)
+            .map_err(|_| SettlementError::InvalidTrade)?;
         if trade.notional_e6 != expected_notional {
             return Err(SettlementError::InvalidTrade.into());
         }
         
-        // 验证手续费非负
-        if trade.taker_fee_e6 < 0 {
+        // 验证tick/lot/杠杆
+        if !market.limits.allows(trade) {
+            return Err(SettlementError::InvalidTrade.into());
+        }
+        
+        // 验证taker手续费非负
+        if trade.taker_fee_e6.is_negative() {
+            return Err(SettlementError::InvalidTrade.into());
+        }
+        
+        // 验证maker手续费与费率同号（返佣费率对应负手续费；小额成交可能取整为0）
+        if !maker_fee_matches_rate_sign(trade.maker_fee_e6, trade.fee_rate_maker_e5) {
             return Err(SettlementError::InvalidTrade.into());
         }
     }
@@ -36,6 +56,198 @@ pub fn validate_settlement_data(trades: &[CompleteTrade]) -> ProgramResult {
     Ok(())
 }
 
+/// maker手续费与费率的符号是否一致（手续费为0时总是一致）
+fn maker_fee_matches_rate_sign(fee: UsdcE6, rate: i32) -> bool {
+    fee == UsdcE6::ZERO || fee.0.signum() == rate.signum() as i64
+}
+
+/// 计算批次汇总：(总成交额, 总手续费)
+///
+/// 成交额为Σ notional_e6（链上已按市场参数校验每个notional）
+pub fn calculate_batch_totals(trades: &[CompleteTrade]) -> Result<(UsdcE6, UsdcE6), SettlementError> {
+    let mut total_volume_e6 = UsdcE6::ZERO;
+    let mut total_fees_e6 = UsdcE6::ZERO;
+    
+    for trade in trades {
+        total_volume_e6 = total_volume_e6.checked_add(trade.notional_e6)?;
+        total_fees_e6 = total_fees_e6
+            .checked_add(trade.taker_fee_e6)?
+            .checked_add(trade.maker_fee_e6)?;
+    }
+    
+    Ok((total_volume_e6, total_fees_e6))
+}
+
+/// 计算批次数据hash：sha256(borsh(trades))
+pub fn calculate_batch_hash(trades: &[CompleteTrade]) -> Result<[u8; 32], SettlementError> {
+    let encoded = trades.try_to_vec()?;
+    Ok(Sha256::digest(&encoded).into())
+}
+
+/// 验证调用方提交的批次汇总和hash与链上重新计算的结果一致
+pub fn verify_batch_commitment(
+    trades: &[CompleteTrade],
+    total_volume_e6: UsdcE6,
+    total_fees_e6: UsdcE6,
+    data_hash: &[u8; 32],
+) -> ProgramResult {
+    let (expected_volume_e6, expected_fees_e6) = calculate_batch_totals(trades)?;
+    
+    if total_volume_e6 != expected_volume_e6 {
+        return Err(SettlementError::InvalidTotalVolume.into());
+    }
+    
+    if total_fees_e6 != expected_fees_e6 {
+        return Err(SettlementError::InvalidTotalFees.into());
+    }
+    
+    if data_hash != &calculate_batch_hash(trades)? {
+        return Err(SettlementError::InvalidDataHash.into());
+    }
+    
+    Ok(())
+}
+
+/// 验证trades的engine_seq严格递增且接续last_engine_seq，返回新的last_engine_seq
+///
+/// last_engine_seq为0表示尚未结算任何trade，此时第一笔trade的序号作为起点。
+/// engine_seq从1开始：序号0与该哨兵值冲突，按重放拒绝。
+/// allow_gaps为false时要求序号连续（每次+1）。
+pub fn validate_engine_sequence(
+    trades: &[CompleteTrade],
+    last_engine_seq: u64,
+    allow_gaps: bool,
+) -> Result<u64, SettlementError> {
+    let mut last = last_engine_seq;
+    
+    for trade in trades {
+        if trade.engine_seq <= last {
+            return Err(SettlementError::SequenceReplay);
+        }
+        if last != 0 && !allow_gaps && trade.engine_seq != last + 1 {
+            return Err(SettlementError::SequenceGap);
+        }
+        last = trade.engine_seq;
+    }
+    
+    Ok(last)
+}
+
+/// 为trades涉及的wallet建立索引
+///
+/// 返回 (唯一wallet列表, 每个trade的(taker_index, maker_index))。
+/// wallet按首次出现顺序排列（同一trade内先taker后maker），
+/// 即RecordSettlement中UserSettlement accounts的顺序。
+pub fn index_trade_wallets(trades: &[CompleteTrade]) -> (Vec<Pubkey>, Vec<(usize, usize)>) {
+    let mut wallets: Vec<Pubkey> = Vec::new();
+    let mut indices = Vec::with_capacity(trades.len());
+    
+    let index_of = |wallet: &Pubkey, wallets: &mut Vec<Pubkey>| {
+        match wallets.iter().position(|w| w == wallet) {
+            Some(idx) => idx,
+            None => {
+                wallets.push(*wallet);
+                wallets.len() - 1
+            }
+        }
+    };
+    
+    for trade in trades {
+        let taker_idx = index_of(&trade.taker_wallet, &mut wallets);
+        let maker_idx = index_of(&trade.maker_wallet, &mut wallets);
+        indices.push((taker_idx, maker_idx));
+    }
+    
+    (wallets, indices)
+}
+
+/// 为trades涉及的市场建立索引
+///
+/// 返回 (唯一市场列表, 每个trade的市场index)，市场按首次出现顺序排列，
+/// 即RecordSettlement中Market accounts的顺序。
+pub fn index_trade_markets(trades: &[CompleteTrade]) -> (Vec<&str>, Vec<usize>) {
+    let mut markets: Vec<&str> = Vec::new();
+    let mut indices = Vec::with_capacity(trades.len());
+    
+    for trade in trades {
+        let idx = match markets.iter().position(|m| *m == trade.market) {
+            Some(idx) => idx,
+            None => {
+                markets.push(&trade.market);
+                markets.len() - 1
+            }
+        };
+        indices.push(idx);
+    }
+    
+    (markets, indices)
+}
+
+/// 每个trade的(taker index, maker index)
+pub type TakerMakerIndex = (usize, usize);
+
+/// 为trades涉及的(用户, 市场)组合建立索引
+///
+/// 输入为 `index_trade_wallets` / `index_trade_markets` 返回的每个trade的index。
+/// 返回 (唯一的(wallet index, market index)列表, 每个trade的(taker组合index, maker组合index))，
+/// 组合按首次出现顺序排列（同一trade中taker在前），即RecordSettlement中UserMarketStats accounts的顺序。
+pub fn index_trade_user_markets(
+    trade_user_indices: &[TakerMakerIndex],
+    trade_market_indices: &[usize],
+) -> (Vec<(usize, usize)>, Vec<TakerMakerIndex>) {
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    let mut indices = Vec::with_capacity(trade_user_indices.len());
+    
+    let index_of = |pair: (usize, usize), pairs: &mut Vec<(usize, usize)>| {
+        match pairs.iter().position(|p| *p == pair) {
+            Some(idx) => idx,
+            None => {
+                pairs.push(pair);
+                pairs.len() - 1
+            }
+        }
+    };
+    
+    for (&(taker_idx, maker_idx), &market_idx) in trade_user_indices.iter().zip(trade_market_indices) {
+        let taker_pair = index_of((taker_idx, market_idx), &mut pairs);
+        let maker_pair = index_of((maker_idx, market_idx), &mut pairs);
+        indices.push((taker_pair, maker_pair));
+    }
+    
+    (pairs, indices)
+}
+
+/// 为batch中taker的推荐人建立索引
+///
+/// user_referrers按 `index_trade_wallets` 返回的wallet顺序给出每个用户的推荐人。
+/// 返回 (唯一的推荐人列表, 每个用户的推荐人index)，推荐人按其taker首次出现顺序排列，
+/// 即RecordSettlement中ReferrerStats accounts的顺序；只作为maker出现的用户不计入（index为None）。
+pub fn index_trade_referrers(
+    user_referrers: &[Option<Pubkey>],
+    trade_user_indices: &[TakerMakerIndex],
+) -> (Vec<Pubkey>, Vec<Option<usize>>) {
+    let mut referrers: Vec<Pubkey> = Vec::new();
+    let mut indices = vec![None; user_referrers.len()];
+    
+    for &(taker_idx, _) in trade_user_indices {
+        let Some(referrer) = user_referrers[taker_idx] else { continue };
+        if indices[taker_idx].is_some() {
+            continue;
+        }
+        
+        let idx = match referrers.iter().position(|r| *r == referrer) {
+            Some(idx) => idx,
+            None => {
+                referrers.push(referrer);
+                referrers.len() - 1
+            }
+        };
+        indices[taker_idx] = Some(idx);
+    }
+    
+    (referrers, indices)
+}
+
 /// 验证batch_id格式（UUID）
 pub fn validate_batch_id(batch_id: &str) -> ProgramResult {
     // 简单验证：UUID格式为 xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx
@@ -56,19 +268,57 @@ pub fn validate_batch_id(batch_id: &str) -> ProgramResult {
     Ok(())
 }
 
+/// 解析Batch ID为16字节UUID（字符串形式先经过validate_batch_id校验）
+pub fn parse_batch_id(batch_id: &BatchId) -> Result<[u8; 16], SettlementError> {
+    match batch_id {
+        BatchId::Binary(bytes) => Ok(*bytes),
+        BatchId::Uuid(uuid) => {
+            validate_batch_id(uuid).map_err(|_| SettlementError::InvalidBatchId)?;
+            
+            let hex: Vec<u8> = uuid.bytes().filter(|b| *b != b'-').collect();
+            let mut bytes = [0u8; 16];
+            for (i, pair) in hex.chunks(2).enumerate() {
+                let pair = std::str::from_utf8(pair).map_err(|_| SettlementError::InvalidBatchId)?;
+                bytes[i] = u8::from_str_radix(pair, 16).map_err(|_| SettlementError::InvalidBatchId)?;
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+/// 16字节UUID格式化为小写UUID字符串（用于日志）
+pub fn format_batch_id(bytes: &[u8; 16]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::state::{CompleteTrade, Side};
-    use solana_program::pubkey::Pubkey;
+    use crate::{
+        amount::{PriceE6, QtyE6},
+        state::{CompleteTrade, MarketLimits, MarketParams, Side},
+    };
+    
+    fn btc_limits() -> MarketLimits {
+        MarketLimits {
+            tick_size: PriceE6(100_000),
+            lot_size: QtyE6(1_000),
+            max_leverage: 50,
+        }
+    }
+    
+    fn btc_market() -> Market {
+        Market::new("BTC-PERP".to_string(), 0, 255, MarketParams::STANDARD, btc_limits(), 0)
+    }
     
     fn create_test_trade() -> CompleteTrade {
         CompleteTrade {
             id: "9811e894-5368-4c1a-8fe3-d149d92279f9".to_string(),
             market: "BTC-PERP".to_string(),
-            price_e6: 105315000000,
-            qty_e6: 1000,
-            notional_e6: 105315000,
+            price_e6: PriceE6(105315000000),
+            qty_e6: QtyE6(1000),
+            notional_e6: UsdcE6(105315000),
             taker_side: Side::Sell,
             ts_ms: 1762897603000,
             engine_seq: 7,
@@ -80,37 +330,250 @@ mod tests {
             maker_wallet: Pubkey::default(),
             taker_leverage: 20,
             maker_leverage: 20,
-            taker_fee_e6: 47391,
-            maker_fee_e6: 15797,
-            fee_rate_taker_bp: 45,
-            fee_rate_maker_bp: 15,
+            taker_fee_e6: UsdcE6(47391),
+            maker_fee_e6: UsdcE6(15797),
+            fee_rate_taker_e5: 45,
+            fee_rate_maker_e5: 15,
         }
     }
     
     #[test]
     fn test_validate_correct_trade() {
         let trades = vec![create_test_trade()];
-        assert!(validate_settlement_data(&trades).is_ok());
+        assert!(validate_settlement_data(&trades, &[btc_market()]).is_ok());
     }
     
     #[test]
     fn test_validate_empty_trades() {
         let trades: Vec<CompleteTrade> = vec![];
-        assert!(validate_settlement_data(&trades).is_err());
+        assert!(validate_settlement_data(&trades, &[btc_market()]).is_err());
     }
     
     #[test]
     fn test_validate_invalid_price() {
         let mut trade = create_test_trade();
-        trade.price_e6 = 0;
-        assert!(validate_settlement_data(&vec![trade]).is_err());
+        trade.price_e6 = PriceE6::ZERO;
+        assert!(validate_settlement_data(&[trade], &[btc_market()]).is_err());
     }
     
     #[test]
     fn test_validate_invalid_notional() {
         let mut trade = create_test_trade();
-        trade.notional_e6 = 999999;  // 错误的notional
-        assert!(validate_settlement_data(&vec![trade]).is_err());
+        trade.notional_e6 = UsdcE6(999999);  // 错误的notional
+        assert!(validate_settlement_data(&[trade], &[btc_market()]).is_err());
+    }
+    
+    #[test]
+    fn test_validate_uses_market_params() {
+        let mut trade = create_test_trade();
+        trade.market = "ETH-PERP".to_string();
+        
+        // 未登记的市场
+        assert_eq!(
+            validate_settlement_data(&[trade.clone()], &[btc_market()]),
+            Err(SettlementError::UnknownMarket.into())
+        );
+        
+        // 数量3位小数的市场：同样的price/qty，notional放大1000倍
+        let params = MarketParams { base_decimals: 3, ..MarketParams::STANDARD };
+        let eth = Market::new("ETH-PERP".to_string(), 1, 255, params, btc_limits(), 0);
+        let markets = vec![btc_market(), eth];
+        assert!(validate_settlement_data(&[trade.clone()], &markets).is_err());
+        
+        trade.notional_e6 = UsdcE6(105315000 * 1000);
+        assert!(validate_settlement_data(&[trade], &markets).is_ok());
+    }
+    
+    #[test]
+    fn test_validate_maker_fee_sign() {
+        let markets = [btc_market()];
+        
+        // 返佣：负费率 + 负手续费
+        let mut trade = create_test_trade();
+        trade.fee_rate_maker_e5 = -5;
+        trade.maker_fee_e6 = UsdcE6(-5266);
+        assert!(validate_settlement_data(&[trade.clone()], &markets).is_ok());
+        
+        // 返佣费率但手续费为正
+        trade.maker_fee_e6 = UsdcE6(5266);
+        assert!(validate_settlement_data(&[trade.clone()], &markets).is_err());
+        
+        // 正费率但手续费为负
+        trade.fee_rate_maker_e5 = 15;
+        trade.maker_fee_e6 = UsdcE6(-15797);
+        assert!(validate_settlement_data(&[trade.clone()], &markets).is_err());
+        
+        // 0费率只能对应0手续费
+        trade.fee_rate_maker_e5 = 0;
+        assert!(validate_settlement_data(&[trade.clone()], &markets).is_err());
+        trade.maker_fee_e6 = UsdcE6::ZERO;
+        assert!(validate_settlement_data(&[trade], &markets).is_ok());
+    }
+    
+    #[test]
+    fn test_validate_market_limits() {
+        let markets = [btc_market()];
+        assert!(validate_settlement_data(&[create_test_trade()], &markets).is_ok());
+        
+        // 价格不是tick的整数倍
+        let mut trade = create_test_trade();
+        trade.price_e6 = PriceE6(105315050000);
+        trade.notional_e6 = UsdcE6(105315050);
+        assert!(validate_settlement_data(&[trade], &markets).is_err());
+        
+        // 数量不是lot的整数倍
+        let mut trade = create_test_trade();
+        trade.qty_e6 = QtyE6(1500);
+        trade.notional_e6 = UsdcE6(157972500);
+        assert!(validate_settlement_data(&[trade], &markets).is_err());
+        
+        // 杠杆超限
+        let mut trade = create_test_trade();
+        trade.maker_leverage = 51;
+        assert!(validate_settlement_data(&[trade], &markets).is_err());
+        
+        let mut trade = create_test_trade();
+        trade.taker_leverage = 0;
+        assert!(validate_settlement_data(&[trade], &markets).is_err());
+    }
+    
+    #[test]
+    fn test_index_trade_markets() {
+        let mut trades = vec![create_test_trade(), create_test_trade(), create_test_trade()];
+        trades[1].market = "ETH-PERP".to_string();
+        let (markets, indices) = index_trade_markets(&trades);
+        assert_eq!(markets, vec!["BTC-PERP", "ETH-PERP"]);
+        assert_eq!(indices, vec![0, 1, 0]);
+    }
+    
+    #[test]
+    fn test_index_trade_user_markets() {
+        // trade 0: A/B @ BTC, trade 1: B/A @ ETH, trade 2: A/B @ BTC
+        let user_indices = vec![(0, 1), (1, 0), (0, 1)];
+        let market_indices = vec![0, 1, 0];
+        let (pairs, indices) = index_trade_user_markets(&user_indices, &market_indices);
+        assert_eq!(pairs, vec![(0, 0), (1, 0), (1, 1), (0, 1)]);
+        assert_eq!(indices, vec![(0, 1), (2, 3), (0, 1)]);
+    }
+    
+    #[test]
+    fn test_index_trade_referrers() {
+        // 用户: A(推荐人R1), B(R2), C(R1), D(无)
+        // trade 0: B/A, trade 1: D/C, trade 2: C/B, trade 3: B/D
+        let r1 = Pubkey::new_unique();
+        let r2 = Pubkey::new_unique();
+        let user_referrers = vec![Some(r1), Some(r2), Some(r1), None];
+        let user_indices = vec![(1, 0), (3, 2), (2, 1), (1, 3)];
+        let (referrers, indices) = index_trade_referrers(&user_referrers, &user_indices);
+        assert_eq!(referrers, vec![r2, r1]);
+        // A只作为maker出现
+        assert_eq!(indices, vec![None, Some(0), Some(1), None]);
+    }
+    
+    #[test]
+    fn test_verify_batch_commitment() {
+        let trades = vec![create_test_trade()];
+        let (volume, fees) = calculate_batch_totals(&trades).unwrap();
+        let hash = calculate_batch_hash(&trades).unwrap();
+        
+        assert_eq!(volume, UsdcE6(105315000));
+        assert_eq!(fees, UsdcE6(47391 + 15797));
+        assert!(verify_batch_commitment(&trades, volume, fees, &hash).is_ok());
+        
+        assert_eq!(
+            verify_batch_commitment(&trades, UsdcE6(volume.0 + 1), fees, &hash),
+            Err(SettlementError::InvalidTotalVolume.into())
+        );
+        assert_eq!(
+            verify_batch_commitment(&trades, volume, UsdcE6(fees.0 - 1), &hash),
+            Err(SettlementError::InvalidTotalFees.into())
+        );
+        assert_eq!(
+            verify_batch_commitment(&trades, volume, fees, &[0u8; 32]),
+            Err(SettlementError::InvalidDataHash.into())
+        );
+    }
+    
+    #[test]
+    fn test_batch_hash_depends_on_trade_order() {
+        let first = create_test_trade();
+        let mut second = create_test_trade();
+        second.engine_seq = 8;
+        
+        let forward = calculate_batch_hash(&[first.clone(), second.clone()]).unwrap();
+        let reverse = calculate_batch_hash(&[second, first]).unwrap();
+        assert_ne!(forward, reverse);
+    }
+    
+    fn trades_with_seqs(seqs: &[u64]) -> Vec<CompleteTrade> {
+        seqs.iter()
+            .map(|seq| {
+                let mut trade = create_test_trade();
+                trade.engine_seq = *seq;
+                trade
+            })
+            .collect()
+    }
+    
+    #[test]
+    fn test_validate_engine_sequence() {
+        // 首个batch作为起点
+        assert_eq!(validate_engine_sequence(&trades_with_seqs(&[7, 8, 9]), 0, false), Ok(9));
+        
+        // 跨batch接续
+        assert_eq!(validate_engine_sequence(&trades_with_seqs(&[10, 11]), 9, false), Ok(11));
+        assert_eq!(
+            validate_engine_sequence(&trades_with_seqs(&[12]), 9, false),
+            Err(SettlementError::SequenceGap)
+        );
+        assert_eq!(validate_engine_sequence(&trades_with_seqs(&[12]), 9, true), Ok(12));
+        
+        // 重放或乱序
+        assert_eq!(
+            validate_engine_sequence(&trades_with_seqs(&[9]), 9, true),
+            Err(SettlementError::SequenceReplay)
+        );
+        assert_eq!(
+            validate_engine_sequence(&trades_with_seqs(&[10, 12, 11]), 9, true),
+            Err(SettlementError::SequenceReplay)
+        );
+        assert_eq!(
+            validate_engine_sequence(&trades_with_seqs(&[3, 3]), 0, true),
+            Err(SettlementError::SequenceReplay)
+        );
+        
+        // 序号0与“尚未结算”冲突：[0, 0]不能通过，也不能把last_engine_seq留在0
+        assert_eq!(
+            validate_engine_sequence(&trades_with_seqs(&[0, 0]), 0, true),
+            Err(SettlementError::SequenceReplay)
+        );
+        assert_eq!(
+            validate_engine_sequence(&trades_with_seqs(&[0]), 0, false),
+            Err(SettlementError::SequenceReplay)
+        );
+        assert_eq!(
+            validate_engine_sequence(&trades_with_seqs(&[0, 1]), 0, false),
+            Err(SettlementError::SequenceReplay)
+        );
+    }
+    
+    #[test]
+    fn test_index_trade_wallets_dedups() {
+        let mm = Pubkey::new_unique();
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        
+        let mut trades = Vec::new();
+        for taker in [a, b, a] {
+            let mut trade = create_test_trade();
+            trade.taker_wallet = taker;
+            trade.maker_wallet = mm;
+            trades.push(trade);
+        }
+        
+        let (wallets, indices) = index_trade_wallets(&trades);
+        assert_eq!(wallets, vec![a, mm, b]);
+        assert_eq!(indices, vec![(0, 1), (2, 1), (0, 1)]);
     }
     
     #[test]
@@ -119,5 +582,23 @@ mod tests {
         assert!(validate_batch_id("invalid-id").is_err());
         assert!(validate_batch_id("").is_err());
     }
+    
+    #[test]
+    fn test_parse_batch_id_forms_match() {
+        let uuid = "79307220-9abf-4f14-a22d-e8b5eebbc40b";
+        let bytes = parse_batch_id(&BatchId::Uuid(uuid.to_string())).unwrap();
+        assert_eq!(bytes[..4], [0x79, 0x30, 0x72, 0x20]);
+        assert_eq!(parse_batch_id(&BatchId::Binary(bytes)).unwrap(), bytes);
+        assert_eq!(
+            parse_batch_id(&BatchId::Uuid(uuid.to_uppercase())).unwrap(),
+            bytes
+        );
+        assert_eq!(format_batch_id(&bytes), uuid);
+        
+        assert_eq!(
+            parse_batch_id(&BatchId::Uuid("x".repeat(10_000))),
+            Err(SettlementError::InvalidBatchId)
+        );
+    }
 }
 
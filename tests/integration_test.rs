@@ -1,108 +1,1889 @@
 //! Integration tests for Settlement Program
 
-use borsh::BorshSerialize;
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::Zeroable;
 use settlement_program::{
-    processor, CompleteTrade, SettlementData, SettlementInstruction, Side,
+    instruction, merkle::MerkleTree, processor, utils::parse_batch_id, BatchId, CompleteTrade, FeeTier, FundingEpoch,
+    FundingPayment, GlobalStats, LegacyUserSettlement, Market,
+    MarketLimits, MarketParams, MarketStats, MarketStatus, PriceE6, ProgramConfig, QtyE6, ReferrerStats, RelayerStatus, SettlementBatch,
+    SettlementError,
+    SettlementInstruction, Side, StatsTracking, UsdcE6, UserMarketStats, UserSettlement, WideUsdcE6,
+};
+use solana_program::{
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    instruction::{AccountMeta, InstructionError},
+    pubkey::Pubkey,
+    system_instruction,
 };
-use solana_program::{pubkey::Pubkey, system_program};
 use solana_program_test::*;
 use solana_sdk::{
-    signature::{Keypair, Signer},
-    transaction::Transaction,
+    account::Account,
+    address_lookup_table::AddressLookupTableAccount,
+    hash::Hash,
+    instruction::Instruction,
+    message::VersionedMessage,
+    packet::PACKET_DATA_SIZE,
+    signature::{Keypair, Signature, Signer},
+    transaction::{Transaction, TransactionError, VersionedTransaction},
 };
 
-#[tokio::test]
-async fn test_record_settlement_success() {
-    // 创建测试环境
-    let program_id = Pubkey::new_unique();
-    let mut program_test = ProgramTest::new(
-        "settlement_program",
-        program_id,
-        processor!(processor::process_instruction),
-    );
-    
-    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
-    
-    // 创建测试settlement数据
-    let batch_id = "79307220-9abf-4f14-a22d-e8b5eebbc40b".to_string();
-    
-    let trade = CompleteTrade {
-        id: "test-trade-1".to_string(),
+const BATCH_ID: &str = "79307220-9abf-4f14-a22d-e8b5eebbc40b";
+
+/// 构造RecordSettlement时使用的market_id：TestContext按顺序登记前三个市场，
+/// 单个测试中额外登记的市场为3（DOGE-PERP不登记，用于测试链上UnknownMarket）
+const MARKET_IDS: &[(&str, u16)] = &[
+    ("BTC-PERP", 0),
+    ("ETH-PERP", 1),
+    ("SOL-PERP", 2),
+    ("kPEPE-PERP", 3),
+    ("DOGE-PERP", 3),
+];
+
+/// 生成第n个测试batch的UUID
+fn batch_id(n: u64) -> String {
+    format!("00000000-0000-4000-8000-{:012x}", n)
+}
+
+struct TestContext {
+    banks_client: BanksClient,
+    payer: Keypair,
+    relayer: Keypair,
+    program_id: Pubkey,
+    last_blockhash: Hash,
+    // 保持模拟PoH任务运行（get_new_latest_blockhash依赖它）
+    _program_test: ProgramTestContext,
+}
+
+impl TestContext {
+    /// 启动测试环境并初始化Config（payer为admin，relayer为唯一授权relayer），
+    /// 登记标准参数的BTC-PERP / ETH-PERP / SOL-PERP（market_id 0 / 1 / 2），
+    /// 设置单档费率（taker 45 / maker 15，误差1）
+    async fn new() -> Self {
+        Self::with_accounts(Vec::new()).await
+    }
+
+    /// 同new，启动前额外写入accounts（如旧版本的account）
+    async fn with_accounts(accounts: Vec<(Pubkey, Account)>) -> Self {
+        let mut ctx = Self::start(accounts).await;
+
+        let ix = instruction::initialize_config(
+            &ctx.program_id,
+            &ctx.payer.pubkey(),
+            vec![ctx.relayer.pubkey()],
+        )
+        .unwrap();
+        ctx.process(&[ix], &[]).await.unwrap();
+
+        for symbol in ["BTC-PERP", "ETH-PERP", "SOL-PERP"] {
+            ctx.register_market(symbol, MarketParams::STANDARD, default_limits()).await.unwrap();
+        }
+
+        let ix = instruction::set_fee_schedule(&ctx.program_id, &ctx.payer.pubkey(), default_fee_tiers(), 1)
+            .unwrap();
+        ctx.process(&[ix], &[]).await.unwrap();
+
+        ctx
+    }
+
+    /// 启动测试环境（未初始化Config），payer为program的upgrade authority
+    async fn start(accounts: Vec<(Pubkey, Account)>) -> Self {
+        let program_id = settlement_program::id();
+        let relayer = Keypair::new();
+
+        let mut program_test = ProgramTest::new(
+            "settlement_program",
+            program_id,
+            processor!(processor::process_instruction),
+        );
+        program_test.add_account(
+            relayer.pubkey(),
+            Account {
+                lamports: 10_000_000_000,
+                ..Account::default()
+            },
+        );
+        for (pubkey, account) in accounts {
+            program_test.add_account(pubkey, account);
+        }
+
+        let mut program_test = program_test.start_with_context().await;
+
+        // processor!注册的program没有ProgramData，按upgradeable loader的格式写入
+        let (program_data, _) = instruction::find_program_data_address(&program_id);
+        let metadata = bincode::serialize(&UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: Some(program_test.payer.pubkey()),
+        })
+        .unwrap();
+        let account = Account {
+            lamports: solana_sdk::rent::Rent::default().minimum_balance(metadata.len()),
+            data: metadata,
+            owner: bpf_loader_upgradeable::id(),
+            ..Account::default()
+        };
+        program_test.set_account(&program_data, &account.into());
+
+        Self {
+            banks_client: program_test.banks_client.clone(),
+            payer: program_test.payer.insecure_clone(),
+            relayer,
+            program_id,
+            last_blockhash: program_test.last_blockhash,
+            _program_test: program_test,
+        }
+    }
+
+    /// 提交交易（payer始终签名）
+    async fn process(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        // 每笔交易使用新的blockhash，保证重试的相同指令是不同交易
+        let recent_blockhash = self
+            .banks_client
+            .get_new_latest_blockhash(&self.last_blockhash)
+            .await?;
+        self.last_blockhash = recent_blockhash;
+        let mut all_signers = vec![&self.payer];
+        all_signers.extend_from_slice(signers);
+
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.payer.pubkey()),
+            &all_signers,
+            recent_blockhash,
+        );
+        self.banks_client.process_transaction(transaction).await
+    }
+
+    /// 从payer转入lamports（新relayer需要支付SettlementBatch租金）
+    async fn fund(&mut self, to: &Pubkey) {
+        let ix = system_instruction::transfer(&self.payer.pubkey(), to, 1_000_000_000);
+        self.process(&[ix], &[]).await.unwrap();
+    }
+
+    async fn register_market(
+        &mut self,
+        symbol: &str,
+        params: MarketParams,
+        limits: MarketLimits,
+    ) -> Result<(), BanksClientError> {
+        let ix = instruction::register_market(
+            &self.program_id,
+            &self.payer.pubkey(),
+            symbol,
+            params,
+            limits,
+        )
+        .unwrap();
+        self.process(&[ix], &[]).await
+    }
+
+    async fn initialize_user(&mut self, wallet: &Pubkey) {
+        let ix = instruction::initialize_user(&self.program_id, &self.relayer.pubkey(), wallet)
+            .unwrap();
+        let relayer = self.relayer.insecure_clone();
+        self.process(&[ix], &[&relayer]).await.unwrap();
+    }
+
+    async fn record_settlement(
+        &mut self,
+        authority: &Keypair,
+        batch_id: &str,
+        trades: Vec<CompleteTrade>,
+    ) -> Result<(), BanksClientError> {
+        self.record_settlement_with_id(authority, BatchId::Uuid(batch_id.to_string()), trades)
+            .await
+    }
+
+    async fn record_settlement_with_id(
+        &mut self,
+        authority: &Keypair,
+        batch_id: BatchId,
+        trades: Vec<CompleteTrade>,
+    ) -> Result<(), BanksClientError> {
+        let ix = instruction::record_settlement(
+            &self.program_id,
+            &authority.pubkey(),
+            batch_id,
+            trades,
+            MARKET_IDS,
+        )
+        .unwrap();
+        self.process(&[ix], &[authority]).await
+    }
+
+    async fn config(&mut self) -> ProgramConfig {
+        let (pda, _) = instruction::find_config_address(&self.program_id);
+        let account = self.banks_client.get_account(pda).await.unwrap().unwrap();
+        ProgramConfig::deserialize(&mut &account.data[..]).unwrap()
+    }
+
+    async fn settlement_batch(&mut self, batch_id: &str) -> SettlementBatch {
+        let bytes = parse_batch_id(&BatchId::Uuid(batch_id.to_string())).unwrap();
+        let (pda, _) = instruction::find_settlement_batch_address(&bytes, &self.program_id);
+        let account = self.banks_client.get_account(pda).await.unwrap().unwrap();
+        SettlementBatch::deserialize(&mut &account.data[..]).unwrap()
+    }
+
+    async fn market(&mut self, symbol: &str) -> Market {
+        let (pda, _) = instruction::find_market_address(symbol, &self.program_id);
+        let account = self.banks_client.get_account(pda).await.unwrap().unwrap();
+        Market::deserialize(&mut &account.data[..]).unwrap()
+    }
+
+    async fn global_stats(&mut self) -> GlobalStats {
+        let (pda, _) = instruction::find_global_stats_address(&self.program_id);
+        let account = self.banks_client.get_account(pda).await.unwrap().unwrap();
+        GlobalStats::try_from_slice(&account.data).unwrap()
+    }
+
+    async fn market_stats(&mut self, symbol: &str) -> MarketStats {
+        let (pda, _) = instruction::find_market_stats_address(symbol, &self.program_id);
+        let account = self.banks_client.get_account(pda).await.unwrap().unwrap();
+        MarketStats::try_from_slice(&account.data).unwrap()
+    }
+
+    async fn user_settlement(&mut self, wallet: &Pubkey) -> UserSettlement {
+        let (pda, _) = instruction::find_user_settlement_address(wallet, &self.program_id);
+        let account = self.banks_client.get_account(pda).await.unwrap().unwrap();
+        UserSettlement::try_from_slice(&account.data).unwrap()
+    }
+
+    async fn user_market_stats(&mut self, wallet: &Pubkey, market_id: u16) -> Option<UserMarketStats> {
+        let (pda, _) = instruction::find_user_market_stats_address(wallet, market_id, &self.program_id);
+        let account = self.banks_client.get_account(pda).await.unwrap()?;
+        Some(UserMarketStats::try_from_slice(&account.data).unwrap())
+    }
+
+    async fn referrer_stats(&mut self, referrer: &Pubkey) -> ReferrerStats {
+        let (pda, _) = instruction::find_referrer_stats_address(referrer, &self.program_id);
+        let account = self.banks_client.get_account(pda).await.unwrap().unwrap();
+        ReferrerStats::try_from_slice(&account.data).unwrap()
+    }
+}
+
+/// 单档费率：taker 0.045%，maker 0.015%（与create_test_trade一致）
+fn default_fee_tiers() -> Vec<FeeTier> {
+    vec![FeeTier {
+        min_volume_e6: UsdcE6::ZERO,
+        taker_rate_e5: 45,
+        maker_rate_e5: 15,
+    }]
+}
+
+/// tick 0.1 USDC, lot 0.001, 最大50倍
+fn default_limits() -> MarketLimits {
+    MarketLimits {
+        tick_size: PriceE6(100_000),
+        lot_size: QtyE6(1_000),
+        max_leverage: 50,
+    }
+}
+
+fn create_test_trade(taker: Pubkey, maker: Pubkey, engine_seq: u64) -> CompleteTrade {
+    CompleteTrade {
+        id: format!("test-trade-{}", engine_seq),
         market: "BTC-PERP".to_string(),
-        price_e6: 105315000000,
-        qty_e6: 1000,
-        notional_e6: 105315000,
+        price_e6: PriceE6(105315000000),
+        qty_e6: QtyE6(1000),
+        notional_e6: UsdcE6(105315000),
         taker_side: Side::Sell,
         ts_ms: 1762897603000,
-        engine_seq: 1,
+        engine_seq,
         taker_order_id: "ord_taker".to_string(),
         maker_order_id: "ord_maker".to_string(),
         taker_account_id: "acc_taker".to_string(),
         maker_account_id: "acc_maker".to_string(),
-        taker_wallet: Pubkey::default(),
-        maker_wallet: Pubkey::default(),
+        taker_wallet: taker,
+        maker_wallet: maker,
         taker_leverage: 20,
         maker_leverage: 20,
-        taker_fee_e6: 47391,
-        maker_fee_e6: 15797,
-        fee_rate_taker_bp: 45,
-        fee_rate_maker_bp: 15,
+        taker_fee_e6: UsdcE6(47391),
+        maker_fee_e6: UsdcE6(15797),
+        fee_rate_taker_e5: 45,
+        fee_rate_maker_e5: 15,
+    }
+}
+
+/// 与docs/TRADE_FIELDS.md示例相同长度的字符串字段（Borsh编码391字节），用于测量交易大小和CU
+fn create_production_trade(taker: Pubkey, maker: Pubkey, engine_seq: u64) -> CompleteTrade {
+    CompleteTrade {
+        id: "9811e894-5368-4c1a-8fe3-d149d92279f9".to_string(),
+        taker_order_id: "ord_80cddb72-e3b2-4d5f-8ebb-0256c21b1ed4".to_string(),
+        maker_order_id: "ord_e95fb572-a637-4498-a61d-63567099b2af".to_string(),
+        taker_account_id: "sol_9ocm9zv5F2QghKaFSLGSjkVg6f8XZf54nVTjfC2M3dG4_main".to_string(),
+        maker_account_id: "sol_G23icA8QJiAM2UwENf1112rGFxoqHP6JJa3TuEDUs7Bo_main".to_string(),
+        ..create_test_trade(taker, maker, engine_seq)
+    }
+}
+
+fn assert_settlement_error(result: Result<(), BanksClientError>, expected: SettlementError) {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            assert_eq!(code, expected as u32, "unexpected error code");
+        }
+        other => panic!("expected {:?}, got {:?}", expected, other),
+    }
+}
+
+#[tokio::test]
+async fn test_record_settlement_success() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    let relayer = ctx.relayer.insecure_clone();
+    let result = ctx
+        .record_settlement(
+            &relayer,
+            BATCH_ID,
+            vec![create_test_trade(taker, maker, 1)],
+        )
+        .await;
+    assert!(result.is_ok(), "Settlement recording failed: {:?}", result.err());
+
+    let taker_stats = ctx.user_settlement(&taker).await;
+    assert_eq!(taker_stats.taker_trades, 1);
+    assert_eq!(taker_stats.taker_volume_e6, WideUsdcE6::new(105315000));
+    assert_eq!(taker_stats.taker_fees_e6, WideUsdcE6::new(47391));
+
+    let maker_stats = ctx.user_settlement(&maker).await;
+    assert_eq!(maker_stats.maker_trades, 1);
+    assert_eq!(maker_stats.maker_fees_e6, WideUsdcE6::new(15797));
+}
+
+#[tokio::test]
+async fn test_repeated_wallets_are_passed_once() {
+    let mut ctx = TestContext::new().await;
+    let maker = Pubkey::new_unique();
+    let taker_a = Pubkey::new_unique();
+    let taker_b = Pubkey::new_unique();
+
+    for wallet in [maker, taker_a, taker_b] {
+        ctx.initialize_user(&wallet).await;
+    }
+
+    // 同一个maker出现在所有trades中，taker_a还同时做了一笔maker
+    let trades = vec![
+        create_test_trade(taker_a, maker, 1),
+        create_test_trade(taker_b, maker, 2),
+        create_test_trade(taker_a, maker, 3),
+        create_test_trade(taker_b, taker_a, 4),
+    ];
+
+    let relayer = ctx.relayer.insecure_clone();
+    let ix = instruction::record_settlement(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        BatchId::Uuid(BATCH_ID.to_string()),
+        trades,
+        MARKET_IDS,
+    )
+    .unwrap();
+    // relayer, config, batch, system program, global stats, fee schedule
+    // + 3个唯一用户 + 1个市场 + 3个(用户, 市场)组合 + 1个MarketStats
+    assert_eq!(ix.accounts.len(), 6 + 3 + 1 + 3 + 1);
+
+    let result = ctx.process(&[ix], &[&relayer]).await;
+    assert!(result.is_ok(), "Settlement recording failed: {:?}", result.err());
+
+    let maker_stats = ctx.user_settlement(&maker).await;
+    assert_eq!(maker_stats.total_trades, 3);
+    assert_eq!(maker_stats.maker_trades, 3);
+    assert_eq!(maker_stats.maker_fees_e6, WideUsdcE6::new(3 * 15797));
+
+    let taker_a_stats = ctx.user_settlement(&taker_a).await;
+    assert_eq!(taker_a_stats.total_trades, 3);
+    assert_eq!(taker_a_stats.taker_trades, 2);
+    assert_eq!(taker_a_stats.maker_trades, 1);
+    assert_eq!(taker_a_stats.taker_volume_e6, WideUsdcE6::new(2 * 105315000));
+
+    let taker_b_stats = ctx.user_settlement(&taker_b).await;
+    assert_eq!(taker_b_stats.taker_trades, 2);
+    assert_eq!(taker_b_stats.taker_fees_e6, WideUsdcE6::new(2 * 47391));
+}
+
+#[tokio::test]
+async fn test_swapped_user_accounts_are_rejected() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    let relayer = ctx.relayer.insecure_clone();
+    let mut ix = instruction::record_settlement(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        BatchId::Uuid(BATCH_ID.to_string()),
+        vec![create_test_trade(taker, maker, 1)],
+        MARKET_IDS,
+    )
+    .unwrap();
+    // 两个account都属于program且bump有效，但与wallet顺序不符
+    ix.accounts.swap(6, 7);
+
+    let result = ctx.process(&[ix], &[&relayer]).await;
+    assert_settlement_error(result, SettlementError::InvalidSettlementAccount);
+}
+
+#[tokio::test]
+async fn test_record_settlement_rejects_unauthorized_relayer() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    let impostor = Keypair::new();
+    let result = ctx
+        .record_settlement(
+            &impostor,
+            BATCH_ID,
+            vec![create_test_trade(taker, maker, 1)],
+        )
+        .await;
+    assert_settlement_error(result, SettlementError::InvalidAuthority);
+}
+
+#[tokio::test]
+async fn test_multiple_relayers_with_disable_and_remove() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    // 登记第二个relayer
+    let backup = Keypair::new();
+    ctx.fund(&backup.pubkey()).await;
+    let ix = instruction::add_relayer(
+        &ctx.program_id,
+        &ctx.payer.pubkey(),
+        &backup.pubkey(),
+        "engine-backup".to_string(),
+    )
+    .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    let result = ctx
+        .record_settlement(&backup, BATCH_ID, vec![create_test_trade(taker, maker, 1)])
+        .await;
+    assert!(result.is_ok(), "Settlement recording failed: {:?}", result.err());
+
+    let config = ctx.config().await;
+    let info = config.find_relayer(&backup.pubkey()).unwrap();
+    assert_eq!(info.label, "engine-backup");
+    assert!(info.last_used_at > 0);
+    assert_eq!(config.find_relayer(&ctx.relayer.pubkey()).unwrap().last_used_at, 0);
+
+    // 停用主relayer
+    let ix = instruction::set_relayer_status(
+        &ctx.program_id,
+        &ctx.payer.pubkey(),
+        &ctx.relayer.pubkey(),
+        RelayerStatus::Disabled,
+    )
+    .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    let primary = ctx.relayer.insecure_clone();
+    let result = ctx
+        .record_settlement(&primary, BATCH_ID, vec![create_test_trade(taker, maker, 2)])
+        .await;
+    assert_settlement_error(result, SettlementError::InvalidAuthority);
+
+    // 移除备用relayer
+    let ix = instruction::remove_relayer(&ctx.program_id, &ctx.payer.pubkey(), &backup.pubkey())
+        .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    let result = ctx
+        .record_settlement(&backup, BATCH_ID, vec![create_test_trade(taker, maker, 3)])
+        .await;
+    assert_settlement_error(result, SettlementError::InvalidAuthority);
+}
+
+#[tokio::test]
+async fn test_initialize_config_requires_upgrade_authority() {
+    let mut ctx = TestContext::start(Vec::new()).await;
+    let relayer = ctx.relayer.insecure_clone();
+
+    // 非upgrade authority不能抢先初始化
+    let ix = instruction::initialize_config(&ctx.program_id, &relayer.pubkey(), vec![relayer.pubkey()]).unwrap();
+    let result = ctx.process(&[ix], &[&relayer]).await;
+    assert_settlement_error(result, SettlementError::UnauthorizedAdmin);
+
+    // 传入其他account冒充ProgramData
+    let mut ix = instruction::initialize_config(&ctx.program_id, &relayer.pubkey(), vec![]).unwrap();
+    ix.accounts[4].pubkey = relayer.pubkey();
+    let result = ctx.process(&[ix], &[&relayer]).await;
+    assert_settlement_error(result, SettlementError::InvalidSettlementAccount);
+
+    let admin = ctx.payer.pubkey();
+    let ix = instruction::initialize_config(&ctx.program_id, &admin, vec![relayer.pubkey()]).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    assert_eq!(ctx.config().await.admin, admin);
+
+    let ix = instruction::initialize_config(&ctx.program_id, &admin, vec![]).unwrap();
+    let result = ctx.process(&[ix], &[]).await;
+    assert_settlement_error(result, SettlementError::AccountAlreadyExists);
+}
+
+#[tokio::test]
+async fn test_relayer_management_requires_admin() {
+    let mut ctx = TestContext::new().await;
+
+    let relayer = ctx.relayer.insecure_clone();
+    let ix = instruction::add_relayer(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        &Pubkey::new_unique(),
+        String::new(),
+    )
+    .unwrap();
+    let result = ctx.process(&[ix], &[&relayer]).await;
+    assert_settlement_error(result, SettlementError::UnauthorizedAdmin);
+}
+
+#[tokio::test]
+async fn test_two_step_admin_transfer() {
+    let mut ctx = TestContext::new().await;
+    let new_admin = Keypair::new();
+    let stranger = Keypair::new();
+
+    let ix = instruction::propose_admin(&ctx.program_id, &ctx.payer.pubkey(), &new_admin.pubkey())
+        .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    let config = ctx.config().await;
+    assert_eq!(config.admin, ctx.payer.pubkey());
+    assert_eq!(config.pending_admin, Some(new_admin.pubkey()));
+
+    // 只有pending admin可以接受
+    let ix = instruction::accept_admin(&ctx.program_id, &stranger.pubkey()).unwrap();
+    let result = ctx.process(&[ix], &[&stranger]).await;
+    assert_settlement_error(result, SettlementError::UnauthorizedAdmin);
+
+    let ix = instruction::accept_admin(&ctx.program_id, &new_admin.pubkey()).unwrap();
+    ctx.process(&[ix], &[&new_admin]).await.unwrap();
+
+    let config = ctx.config().await;
+    assert_eq!(config.admin, new_admin.pubkey());
+    assert_eq!(config.pending_admin, None);
+
+    // 旧admin失去权限
+    let ix = instruction::add_relayer(
+        &ctx.program_id,
+        &ctx.payer.pubkey(),
+        &Pubkey::new_unique(),
+        String::new(),
+    )
+    .unwrap();
+    let result = ctx.process(&[ix], &[]).await;
+    assert_settlement_error(result, SettlementError::UnauthorizedAdmin);
+}
+
+#[tokio::test]
+async fn test_pause_blocks_settlement_but_not_admin() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+
+    let ix = instruction::pause(&ctx.program_id, &ctx.payer.pubkey(), None).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    // 所有非admin的写指令都被拒绝
+    let relayer = ctx.relayer.insecure_clone();
+    let ix = instruction::initialize_user(&ctx.program_id, &relayer.pubkey(), &maker).unwrap();
+    let result = ctx.process(&[ix], &[&relayer]).await;
+    assert_settlement_error(result, SettlementError::ProgramPaused);
+
+    let result = ctx
+        .record_settlement(&relayer, BATCH_ID, vec![create_test_trade(taker, maker, 1)])
+        .await;
+    assert_settlement_error(result, SettlementError::ProgramPaused);
+
+    let payments = vec![FundingPayment { wallet: taker, amount_e6: UsdcE6::ZERO }];
+    let ix = instruction::record_funding(&ctx.program_id, &relayer.pubkey(), 1, "BTC-PERP", payments)
+        .unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&relayer]).await, SettlementError::ProgramPaused);
+
+    let referred = Keypair::new();
+    ctx.fund(&referred.pubkey()).await;
+    let ix = instruction::set_referrer(&ctx.program_id, &referred.pubkey(), &taker).unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&referred]).await, SettlementError::ProgramPaused);
+
+    // admin指令仍然可用
+    let ix = instruction::add_relayer(
+        &ctx.program_id,
+        &ctx.payer.pubkey(),
+        &Pubkey::new_unique(),
+        String::new(),
+    )
+    .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    let ix = instruction::unpause(&ctx.program_id, &ctx.payer.pubkey(), None).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    ctx.initialize_user(&maker).await;
+    let result = ctx
+        .record_settlement(&relayer, BATCH_ID, vec![create_test_trade(taker, maker, 1)])
+        .await;
+    assert!(result.is_ok(), "Settlement recording failed: {:?}", result.err());
+}
+
+#[tokio::test]
+async fn test_pause_single_market() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    // BTC-PERP = market 0
+    let ix = instruction::pause(&ctx.program_id, &ctx.payer.pubkey(), Some(0)).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    assert!(ctx.config().await.is_market_paused(0));
+
+    let relayer = ctx.relayer.insecure_clone();
+    let result = ctx
+        .record_settlement(&relayer, BATCH_ID, vec![create_test_trade(taker, maker, 1)])
+        .await;
+    assert_settlement_error(result, SettlementError::MarketPaused);
+
+    let mut eth_trade = create_test_trade(taker, maker, 2);
+    eth_trade.market = "ETH-PERP".to_string();
+    let result = ctx.record_settlement(&relayer, BATCH_ID, vec![eth_trade]).await;
+    assert!(result.is_ok(), "Settlement recording failed: {:?}", result.err());
+}
+
+#[tokio::test]
+async fn test_duplicate_batch_is_rejected() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    let relayer = ctx.relayer.insecure_clone();
+    let trades = vec![create_test_trade(taker, maker, 1), create_test_trade(taker, maker, 2)];
+    ctx.record_settlement(&relayer, BATCH_ID, trades.clone()).await.unwrap();
+
+    let batch = ctx.settlement_batch(BATCH_ID).await;
+    assert_eq!(
+        batch.batch_id,
+        parse_batch_id(&BatchId::Uuid(BATCH_ID.to_string())).unwrap()
+    );
+    assert_eq!(batch.trade_count, 2);
+    assert_eq!(batch.total_volume_e6, UsdcE6(2 * 105315000));
+    assert_eq!(batch.total_fees_e6, UsdcE6(2 * (47391 + 15797)));
+    assert_eq!(batch.relayer, relayer.pubkey());
+    assert_eq!(
+        batch.batch_hash,
+        settlement_program::utils::calculate_batch_hash(&trades).unwrap()
+    );
+
+    // relayer重试同一batch（即使改用二进制形式）：失败且统计不变
+    let result = ctx.record_settlement(&relayer, BATCH_ID, trades.clone()).await;
+    assert_settlement_error(result, SettlementError::AccountAlreadyExists);
+
+    let binary = parse_batch_id(&BatchId::Uuid(BATCH_ID.to_string())).unwrap();
+    let result = ctx
+        .record_settlement_with_id(&relayer, BatchId::Binary(binary), trades)
+        .await;
+    assert_settlement_error(result, SettlementError::AccountAlreadyExists);
+
+    let taker_stats = ctx.user_settlement(&taker).await;
+    assert_eq!(taker_stats.taker_trades, 2);
+}
+
+#[tokio::test]
+async fn test_pre_funded_batch_is_not_a_duplicate() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    // 提前向batch PDA转账不能让该batch被当成已记录
+    let binary = parse_batch_id(&BatchId::Uuid(BATCH_ID.to_string())).unwrap();
+    let (pda, _) = instruction::find_settlement_batch_address(&binary, &ctx.program_id);
+    ctx.fund(&pda).await;
+
+    let relayer = ctx.relayer.insecure_clone();
+    ctx.record_settlement(&relayer, BATCH_ID, vec![create_test_trade(taker, maker, 1)])
+        .await
+        .unwrap();
+
+    let batch = ctx.settlement_batch(BATCH_ID).await;
+    assert_eq!(batch.trade_count, 1);
+    assert_eq!(batch.relayer, relayer.pubkey());
+
+    let result = ctx
+        .record_settlement(&relayer, BATCH_ID, vec![create_test_trade(taker, maker, 2)])
+        .await;
+    assert_settlement_error(result, SettlementError::AccountAlreadyExists);
+}
+
+#[tokio::test]
+async fn test_invalid_batch_id_is_rejected() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    // 绕过客户端builder的校验，直接构造指令数据
+    let relayer = ctx.relayer.insecure_clone();
+    let mut ix = instruction::record_settlement(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        BatchId::Uuid(BATCH_ID.to_string()),
+        vec![create_test_trade(taker, maker, 1)],
+        MARKET_IDS,
+    )
+    .unwrap();
+    let SettlementInstruction::RecordSettlement {
+        trades,
+        total_volume_e6,
+        total_fees_e6,
+        data_hash,
+        ..
+    } = SettlementInstruction::try_from_slice(&ix.data).unwrap()
+    else {
+        unreachable!()
     };
-    
-    let total_volume_e6 = 105315000;
-    let total_fees_e6 = 47391 + 15797;
-    
-    let mut settlement_data = SettlementData {
-        batch_id: batch_id.clone(),
-        timestamp_ms: 1762897603000,
-        relayer: payer.pubkey(),
-        trades: vec![trade],
-        accounts: vec![],
-        block_height: 0,
-        tx_signature: [0u8; 64],
+    ix.data = SettlementInstruction::RecordSettlement {
+        batch_id: BatchId::Uuid("not-a-uuid".to_string()),
+        trades,
         total_volume_e6,
         total_fees_e6,
-        data_hash: [0u8; 32],
+        data_hash,
+    }
+    .try_to_vec()
+    .unwrap();
+
+    let result = ctx.process(&[ix], &[&relayer]).await;
+    assert_settlement_error(result, SettlementError::InvalidBatchId);
+}
+
+#[tokio::test]
+async fn test_batch_commitment_mismatch_is_rejected() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    let relayer = ctx.relayer.insecure_clone();
+    let ix = instruction::record_settlement(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        BatchId::Uuid(BATCH_ID.to_string()),
+        vec![create_test_trade(taker, maker, 1)],
+        MARKET_IDS,
+    )
+    .unwrap();
+
+    // 提交的hash与trades不一致（例如传输中trades被篡改）
+    let mut tampered = ix.clone();
+    let mut data = SettlementInstruction::try_from_slice(&ix.data).unwrap();
+    if let SettlementInstruction::RecordSettlement { data_hash, .. } = &mut data {
+        data_hash[0] ^= 0xff;
+    }
+    tampered.data = data.try_to_vec().unwrap();
+    let result = ctx.process(&[tampered], &[&relayer]).await;
+    assert_settlement_error(result, SettlementError::InvalidDataHash);
+
+    // 提交的总成交额不一致
+    let mut tampered = ix.clone();
+    let mut data = SettlementInstruction::try_from_slice(&ix.data).unwrap();
+    if let SettlementInstruction::RecordSettlement { total_volume_e6, .. } = &mut data {
+        total_volume_e6.0 += 1;
+    }
+    tampered.data = data.try_to_vec().unwrap();
+    let result = ctx.process(&[tampered], &[&relayer]).await;
+    assert_settlement_error(result, SettlementError::InvalidTotalVolume);
+
+    ctx.process(&[ix], &[&relayer]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_verify_trade_inclusion() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    let relayer = ctx.relayer.insecure_clone();
+    let trades: Vec<_> = (1..=3).map(|seq| create_test_trade(taker, maker, seq)).collect();
+    ctx.record_settlement(&relayer, BATCH_ID, trades.clone()).await.unwrap();
+
+    let tree = MerkleTree::from_trades(&trades).unwrap();
+    assert_eq!(ctx.settlement_batch(BATCH_ID).await.merkle_root, tree.root());
+
+    let batch_bytes = parse_batch_id(&BatchId::Uuid(BATCH_ID.to_string())).unwrap();
+    let ix = instruction::verify_trade_inclusion(
+        &ctx.program_id,
+        &batch_bytes,
+        trades[2].clone(),
+        tree.proof(2).unwrap(),
+    )
+    .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    // 篡改trade后proof失效
+    let mut forged = trades[2].clone();
+    forged.qty_e6.0 *= 10;
+    let ix = instruction::verify_trade_inclusion(
+        &ctx.program_id,
+        &batch_bytes,
+        forged,
+        tree.proof(2).unwrap(),
+    )
+    .unwrap();
+    let result = ctx.process(&[ix], &[]).await;
+    assert_settlement_error(result, SettlementError::InvalidMerkleProof);
+}
+
+#[tokio::test]
+async fn test_engine_sequence_replay_and_gap() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    let relayer = ctx.relayer.insecure_clone();
+
+    // engine_seq 0与“尚未结算”冲突，不能作为起点
+    let trades = vec![create_test_trade(taker, maker, 0), create_test_trade(taker, maker, 0)];
+    let result = ctx.record_settlement(&relayer, &batch_id(1), trades).await;
+    assert_settlement_error(result, SettlementError::SequenceReplay);
+    let result = ctx
+        .record_settlement(&relayer, &batch_id(1), vec![create_test_trade(taker, maker, 0)])
+        .await;
+    assert_settlement_error(result, SettlementError::SequenceReplay);
+    assert_eq!(ctx.config().await.last_engine_seq, 0);
+
+    let trades = vec![create_test_trade(taker, maker, 1), create_test_trade(taker, maker, 2)];
+    ctx.record_settlement(&relayer, &batch_id(1), trades).await.unwrap();
+    assert_eq!(ctx.config().await.last_engine_seq, 2);
+
+    // 新batch_id但重放已结算的trade
+    let result = ctx
+        .record_settlement(&relayer, &batch_id(2), vec![create_test_trade(taker, maker, 2)])
+        .await;
+    assert_settlement_error(result, SettlementError::SequenceReplay);
+
+    // 跳号
+    let result = ctx
+        .record_settlement(&relayer, &batch_id(2), vec![create_test_trade(taker, maker, 4)])
+        .await;
+    assert_settlement_error(result, SettlementError::SequenceGap);
+
+    // admin允许跳号后通过
+    let ix = instruction::set_allow_sequence_gaps(&ctx.program_id, &ctx.payer.pubkey(), true)
+        .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    ctx.record_settlement(&relayer, &batch_id(2), vec![create_test_trade(taker, maker, 4)])
+        .await
+        .unwrap();
+    assert_eq!(ctx.config().await.last_engine_seq, 4);
+}
+
+#[tokio::test]
+async fn test_register_market() {
+    let mut ctx = TestContext::new().await;
+
+    let btc = ctx.market("BTC-PERP").await;
+    assert_eq!(btc.market_id, 0);
+    assert_eq!(btc.params, MarketParams::STANDARD);
+    assert_eq!(ctx.market("SOL-PERP").await.market_id, 2);
+    assert_eq!(ctx.config().await.market_count, 3);
+
+    let params = MarketParams {
+        contract_multiplier_e6: 1_000_000_000,
+        ..MarketParams::STANDARD
     };
-    
-    // 计算hash
-    settlement_data.data_hash = settlement_program::utils::calculate_data_hash(&settlement_data);
-    
-    // 创建instruction
-    let instruction_data = SettlementInstruction::RecordSettlement {
-        data: settlement_data,
+    ctx.register_market("kPEPE-PERP", params, default_limits()).await.unwrap();
+    let kpepe = ctx.market("kPEPE-PERP").await;
+    assert_eq!(kpepe.market_id, 3);
+    assert_eq!(kpepe.params, params);
+
+    // 重复登记
+    let result = ctx.register_market("BTC-PERP", MarketParams::STANDARD, default_limits()).await;
+    assert_settlement_error(result, SettlementError::AccountAlreadyExists);
+
+    // 参数非法
+    let invalid = MarketParams {
+        contract_multiplier_e6: 0,
+        ..MarketParams::STANDARD
     };
-    
-    let serialized_instruction = borsh::to_vec(&instruction_data).unwrap();
-    
-    // 派生PDA
-    let (settlement_pda, _bump) = Pubkey::find_program_address(
-        &[b"settlement", batch_id.as_bytes()],
-        &program_id,
-    );
-    
-    // 创建transaction
-    let mut transaction = Transaction::new_with_payer(
-        &[solana_program::instruction::Instruction {
-            program_id,
-            accounts: vec![
-                solana_program::instruction::AccountMeta::new(settlement_pda, false),
-                solana_program::instruction::AccountMeta::new(payer.pubkey(), true),
-                solana_program::instruction::AccountMeta::new_readonly(system_program::id(), false),
-            ],
-            data: serialized_instruction,
-        }],
-        Some(&payer.pubkey()),
-    );
-    
-    transaction.sign(&[&payer], recent_blockhash);
-    
-    // 提交
-    let result = banks_client.process_transaction(transaction).await;
-    
-    // 验证成功
+    let result = ctx.register_market("DOGE-PERP", invalid, default_limits()).await;
+    assert_settlement_error(result, SettlementError::InvalidMarketParams);
+
+    let invalid = MarketLimits {
+        lot_size: QtyE6::ZERO,
+        ..default_limits()
+    };
+    let result = ctx.register_market("DOGE-PERP", MarketParams::STANDARD, invalid).await;
+    assert_settlement_error(result, SettlementError::InvalidMarketParams);
+
+    // 非admin
+    let relayer = ctx.relayer.insecure_clone();
+    let ix = instruction::register_market(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        "DOGE-PERP",
+        MarketParams::STANDARD,
+        default_limits(),
+    )
+    .unwrap();
+    let result = ctx.process(&[ix], &[&relayer]).await;
+    assert_settlement_error(result, SettlementError::UnauthorizedAdmin);
+}
+
+#[tokio::test]
+async fn test_unknown_market_is_rejected() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    let mut trade = create_test_trade(taker, maker, 1);
+    trade.market = "DOGE-PERP".to_string();
+
+    let relayer = ctx.relayer.insecure_clone();
+    let result = ctx.record_settlement(&relayer, BATCH_ID, vec![trade]).await;
+    assert_settlement_error(result, SettlementError::UnknownMarket);
+}
+
+#[tokio::test]
+async fn test_notional_uses_market_params() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    // 每张合约1000个PEPE
+    let params = MarketParams {
+        contract_multiplier_e6: 1_000_000_000,
+        ..MarketParams::STANDARD
+    };
+    let limits = MarketLimits {
+        tick_size: PriceE6(1),
+        lot_size: QtyE6(1_000_000),
+        max_leverage: 20,
+    };
+    ctx.register_market("kPEPE-PERP", params, limits).await.unwrap();
+
+    // 10张 @ 0.000012 USDC/PEPE = 0.12 USDC
+    let mut trade = create_test_trade(taker, maker, 1);
+    trade.market = "kPEPE-PERP".to_string();
+    trade.price_e6 = PriceE6(12);
+    trade.qty_e6 = QtyE6(10_000_000);
+    trade.notional_e6 = UsdcE6(120);
+
+    // 按标准公式计算的notional被拒绝
+    let relayer = ctx.relayer.insecure_clone();
+    let result = ctx.record_settlement(&relayer, BATCH_ID, vec![trade.clone()]).await;
+    assert_settlement_error(result, SettlementError::InvalidTrade);
+
+    trade.notional_e6 = UsdcE6(120_000);
+    trade.taker_fee_e6 = UsdcE6(54);
+    trade.maker_fee_e6 = UsdcE6(18);
+    let result = ctx.record_settlement(&relayer, BATCH_ID, vec![trade]).await;
+    assert!(result.is_ok(), "Settlement recording failed: {:?}", result.err());
+
+    let taker_stats = ctx.user_settlement(&taker).await;
+    assert_eq!(taker_stats.taker_volume_e6, WideUsdcE6::new(120_000));
+    assert_eq!(ctx.settlement_batch(BATCH_ID).await.total_volume_e6, UsdcE6(120_000));
+}
+
+#[tokio::test]
+async fn test_delisted_market_is_rejected() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    let admin = ctx.payer.pubkey();
+    let ix = instruction::set_market_status(&ctx.program_id, &admin, "BTC-PERP", MarketStatus::Delisted)
+        .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    assert_eq!(ctx.market("BTC-PERP").await.status, MarketStatus::Delisted);
+
+    let relayer = ctx.relayer.insecure_clone();
+    let result = ctx
+        .record_settlement(&relayer, BATCH_ID, vec![create_test_trade(taker, maker, 1)])
+        .await;
+    assert_settlement_error(result, SettlementError::MarketDelisted);
+
+    // 重新上架后可以结算
+    let ix = instruction::set_market_status(&ctx.program_id, &admin, "BTC-PERP", MarketStatus::Active)
+        .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    let result = ctx
+        .record_settlement(&relayer, BATCH_ID, vec![create_test_trade(taker, maker, 1)])
+        .await;
     assert!(result.is_ok(), "Settlement recording failed: {:?}", result.err());
-    
-    println!("✅ Integration test passed: Settlement recorded successfully!");
+
+    // 非admin不能修改市场
+    let ix = instruction::set_market_status(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        "BTC-PERP",
+        MarketStatus::Delisted,
+    )
+    .unwrap();
+    let result = ctx.process(&[ix], &[&relayer]).await;
+    assert_settlement_error(result, SettlementError::UnauthorizedAdmin);
+}
+
+#[tokio::test]
+async fn test_market_limits_are_enforced() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    // 最大杠杆降到10倍，测试trade为20倍
+    let limits = MarketLimits {
+        max_leverage: 10,
+        ..default_limits()
+    };
+    let ix = instruction::update_market_limits(&ctx.program_id, &ctx.payer.pubkey(), "BTC-PERP", limits)
+        .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    assert_eq!(ctx.market("BTC-PERP").await.limits, limits);
+
+    let relayer = ctx.relayer.insecure_clone();
+    let result = ctx
+        .record_settlement(&relayer, BATCH_ID, vec![create_test_trade(taker, maker, 1)])
+        .await;
+    assert_settlement_error(result, SettlementError::InvalidTrade);
+
+    let mut trade = create_test_trade(taker, maker, 1);
+    trade.taker_leverage = 10;
+    trade.maker_leverage = 5;
+    let result = ctx.record_settlement(&relayer, BATCH_ID, vec![trade]).await;
+    assert!(result.is_ok(), "Settlement recording failed: {:?}", result.err());
+
+    // 按symbol计数
+    assert_eq!(ctx.user_settlement(&taker).await.market_trades, [1, 0, 0]);
+}
+
+#[tokio::test]
+async fn test_user_market_stats_created_lazily() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+    assert!(ctx.user_market_stats(&taker, 0).await.is_none());
+
+    let mut eth_trade = create_test_trade(maker, taker, 2);
+    eth_trade.market = "ETH-PERP".to_string();
+    eth_trade.ts_ms += 1000;
+
+    let relayer = ctx.relayer.insecure_clone();
+    ctx.record_settlement(&relayer, &batch_id(1), vec![create_test_trade(taker, maker, 1), eth_trade])
+        .await
+        .unwrap();
+
+    let btc = ctx.user_market_stats(&taker, 0).await.unwrap();
+    assert_eq!(btc.wallet, taker);
+    assert_eq!(btc.market_id, 0);
+    assert_eq!(btc.taker_trades, 1);
+    assert_eq!(btc.maker_trades, 0);
+    assert_eq!(btc.taker_volume_e6, UsdcE6(105315000));
+    assert_eq!(btc.taker_fees_e6, UsdcE6(47391));
+    assert_eq!(btc.first_trade_ts, 1762897603000);
+
+    let eth = ctx.user_market_stats(&taker, 1).await.unwrap();
+    assert_eq!(eth.maker_trades, 1);
+    assert_eq!(eth.maker_fees_e6, UsdcE6(15797));
+    assert_eq!(eth.last_trade_ts, 1762897604000);
+    assert!(ctx.user_market_stats(&taker, 2).await.is_none());
+
+    // 已存在的account在之后的batch中继续累加
+    let mut trade = create_test_trade(taker, maker, 3);
+    trade.ts_ms += 5000;
+    ctx.record_settlement(&relayer, &batch_id(2), vec![trade]).await.unwrap();
+
+    let btc = ctx.user_market_stats(&taker, 0).await.unwrap();
+    assert_eq!(btc.total_trades, 2);
+    assert_eq!(btc.taker_volume_e6, UsdcE6(2 * 105315000));
+    assert_eq!(btc.first_trade_ts, 1762897603000);
+    assert_eq!(btc.last_trade_ts, 1762897608000);
+
+    let maker_btc = ctx.user_market_stats(&maker, 0).await.unwrap();
+    assert_eq!(maker_btc.maker_trades, 2);
+    assert_eq!(maker_btc.taker_trades, 0);
+}
+
+#[tokio::test]
+async fn test_pre_funded_pdas_are_created() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    // 任何人都可以向尚未创建的PDA转账（余额低于租金）
+    let (user_pda, _) = instruction::find_user_settlement_address(&taker, &ctx.program_id);
+    let (pda, _) = instruction::find_user_market_stats_address(&taker, 0, &ctx.program_id);
+    let ixs = [
+        system_instruction::transfer(&ctx.payer.pubkey(), &user_pda, 1_000_000),
+        system_instruction::transfer(&ctx.payer.pubkey(), &pda, 1_000_000),
+    ];
+    ctx.process(&ixs, &[]).await.unwrap();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+    assert_eq!(ctx.user_settlement(&taker).await.wallet, taker);
+
+    let relayer = ctx.relayer.insecure_clone();
+    ctx.record_settlement(&relayer, BATCH_ID, vec![create_test_trade(taker, maker, 1)])
+        .await
+        .unwrap();
+
+    let account = ctx.banks_client.get_account(pda).await.unwrap().unwrap();
+    assert_eq!(account.owner, ctx.program_id);
+    assert_eq!(account.data.len(), UserMarketStats::SIZE);
+    assert!(account.lamports >= solana_sdk::rent::Rent::default().minimum_balance(UserMarketStats::SIZE));
+
+    let stats = ctx.user_market_stats(&taker, 0).await.unwrap();
+    assert_eq!(stats.wallet, taker);
+    assert_eq!(stats.taker_trades, 1);
+    assert_eq!(ctx.config().await.last_engine_seq, 1);
+}
+
+#[tokio::test]
+async fn test_market_stats_updated_by_settlement() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    let stats = ctx.market_stats("BTC-PERP").await;
+    assert_eq!(stats.market_id, 0);
+    assert_eq!(stats.total_trades, 0);
+
+    let mut high = create_test_trade(taker, maker, 2);
+    high.price_e6 = PriceE6(106000000000);
+    high.notional_e6 = UsdcE6(106000000);
+    high.taker_fee_e6 = UsdcE6(47700);
+    high.maker_fee_e6 = UsdcE6(15900);
+    let mut eth_trade = create_test_trade(maker, taker, 3);
+    eth_trade.market = "ETH-PERP".to_string();
+
+    let relayer = ctx.relayer.insecure_clone();
+    ctx.record_settlement(
+        &relayer,
+        &batch_id(1),
+        vec![create_test_trade(taker, maker, 1), high, eth_trade],
+    )
+    .await
+    .unwrap();
+
+    let btc = ctx.market_stats("BTC-PERP").await;
+    assert_eq!(btc.total_trades, 2);
+    assert_eq!(btc.total_volume_e6, 105315000 + 106000000);
+    assert_eq!(btc.total_fees_e6, 47391 + 15797 + 47700 + 15900);
+    assert_eq!(btc.last_price_e6, PriceE6(106000000000));
+    assert_eq!(btc.last_engine_seq, 2);
+    assert_eq!(btc.period_high_e6, PriceE6(106000000000));
+    assert_eq!(btc.period_low_e6, PriceE6(105315000000));
+
+    let eth = ctx.market_stats("ETH-PERP").await;
+    assert_eq!(eth.market_id, 1);
+    assert_eq!(eth.total_trades, 1);
+    assert_eq!(eth.last_engine_seq, 3);
+    assert_eq!(ctx.market_stats("SOL-PERP").await.total_trades, 0);
+
+    // 下一个batch继续累加
+    ctx.record_settlement(&relayer, &batch_id(2), vec![create_test_trade(taker, maker, 4)])
+        .await
+        .unwrap();
+    let btc = ctx.market_stats("BTC-PERP").await;
+    assert_eq!(btc.total_trades, 3);
+    assert_eq!(btc.last_price_e6, PriceE6(105315000000));
+    assert_eq!(btc.period_high_e6, PriceE6(106000000000));
+}
+
+#[tokio::test]
+async fn test_stats_tracking_can_be_skipped() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+    assert_eq!(ctx.config().await.stats_tracking, StatsTracking::ALL);
+
+    let relayer = ctx.relayer.insecure_clone();
+    let ix = instruction::set_stats_tracking(&ctx.program_id, &relayer.pubkey(), StatsTracking::MINIMAL).unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&relayer]).await, SettlementError::UnauthorizedAdmin);
+
+    let ix = instruction::set_stats_tracking(&ctx.program_id, &ctx.payer.pubkey(), StatsTracking::MINIMAL).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    assert_eq!(ctx.config().await.stats_tracking, StatsTracking::MINIMAL);
+
+    // 跳过的统计不传accounts：只有固定accounts、2个UserSettlement和1个Market
+    let ix = instruction::record_settlement_with_stats(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        BatchId::Uuid(batch_id(1)),
+        vec![create_test_trade(taker, maker, 1)],
+        MARKET_IDS,
+        &[],
+        StatsTracking::MINIMAL,
+    )
+    .unwrap();
+    assert_eq!(ix.accounts.len(), 6 + 2 + 1);
+    ctx.process(&[ix], &[&relayer]).await.unwrap();
+
+    assert_eq!(ctx.user_settlement(&taker).await.taker_trades, 1);
+    assert_eq!(ctx.user_settlement(&maker).await.maker_trades, 1);
+    assert_eq!(ctx.user_settlement(&taker).await.realized_pnl_e6, UsdcE6::ZERO);
+    assert_eq!(ctx.global_stats().await.total_trades, 1);
+    assert!(ctx.user_market_stats(&taker, 0).await.is_none());
+    assert_eq!(ctx.market_stats("BTC-PERP").await.total_trades, 0);
+
+    // 恢复全部统计后缺少accounts的指令被拒绝，跳过期间的trades不补记
+    let ix = instruction::set_stats_tracking(&ctx.program_id, &ctx.payer.pubkey(), StatsTracking::ALL).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    let ix = instruction::record_settlement_with_stats(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        BatchId::Uuid(batch_id(2)),
+        vec![create_test_trade(taker, maker, 2)],
+        MARKET_IDS,
+        &[],
+        StatsTracking::MINIMAL,
+    )
+    .unwrap();
+    let result = ctx.process(&[ix], &[&relayer]).await;
+    assert!(matches!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::NotEnoughAccountKeys)
+    ));
+
+    ctx.record_settlement(&relayer, &batch_id(2), vec![create_test_trade(taker, maker, 2)])
+        .await
+        .unwrap();
+    assert_eq!(ctx.market_stats("BTC-PERP").await.total_trades, 1);
+    assert_eq!(ctx.user_market_stats(&taker, 0).await.unwrap().total_trades, 1);
+    assert_eq!(ctx.user_settlement(&taker).await.taker_trades, 2);
+}
+
+#[tokio::test]
+async fn test_global_stats() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    let stats = ctx.global_stats().await;
+    assert_eq!(stats, GlobalStats::new(stats.bump));
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+    assert_eq!(ctx.global_stats().await.total_users, 2);
+
+    let relayer = ctx.relayer.insecure_clone();
+    let trades = vec![create_test_trade(taker, maker, 1), create_test_trade(maker, taker, 2)];
+    ctx.record_settlement(&relayer, &batch_id(1), trades).await.unwrap();
+    ctx.record_settlement(&relayer, &batch_id(2), vec![create_test_trade(taker, maker, 3)])
+        .await
+        .unwrap();
+
+    let stats = ctx.global_stats().await;
+    assert_eq!(stats.total_batches, 2);
+    assert_eq!(stats.total_trades, 3);
+    assert_eq!(stats.total_volume_e6, 3 * 105315000);
+    assert_eq!(stats.total_taker_fees_e6, 3 * 47391);
+    assert_eq!(stats.total_maker_fees_e6, 3 * 15797);
+    assert_eq!(stats.total_users, 2);
+    assert_eq!(
+        stats.last_batch_id,
+        parse_batch_id(&BatchId::Uuid(batch_id(2))).unwrap()
+    );
+    assert!(stats.last_batch_at > 0);
 }
 
+#[tokio::test]
+async fn test_v1_user_settlement_is_migrated() {
+    let program_id = settlement_program::id();
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    // v1 account：224 bytes，已有3笔交易
+    let (taker_pda, bump) = instruction::find_user_settlement_address(&taker, &program_id);
+    let mut v1 = LegacyUserSettlement::zeroed();
+    v1.discriminator = UserSettlement::DISCRIMINATOR;
+    v1.version = 1;
+    v1.bump = bump;
+    v1.wallet = taker;
+    v1.total_trades = 3;
+    v1.total_volume_e6 = UsdcE6(315945000);
+    v1.maker_fees_e6 = UsdcE6(-15797);
+    v1.first_trade_ts = 1762800000000;
+    let data = bytemuck::bytes_of(&v1)[..UserSettlement::V1_SIZE].to_vec();
+    let v1_account = Account {
+        lamports: solana_sdk::rent::Rent::default().minimum_balance(data.len()),
+        data,
+        owner: program_id,
+        ..Account::default()
+    };
+
+    let mut ctx = TestContext::with_accounts(vec![(taker_pda, v1_account)]).await;
+    ctx.initialize_user(&maker).await;
+
+    let relayer = ctx.relayer.insecure_clone();
+    let trade = create_test_trade(taker, maker, 1);
+    let now_ms = trade.ts_ms;
+    ctx.record_settlement(&relayer, BATCH_ID, vec![trade]).await.unwrap();
+
+    let account = ctx.banks_client.get_account(taker_pda).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), UserSettlement::SIZE);
+    assert!(account.lamports >= solana_sdk::rent::Rent::default().minimum_balance(UserSettlement::SIZE));
+
+    let user = ctx.user_settlement(&taker).await;
+    assert_eq!(user.version, UserSettlement::VERSION);
+    assert_eq!(user.total_trades, 4);
+    assert_eq!(user.total_volume_e6, WideUsdcE6::new(315945000 + 105315000));
+    assert_eq!(user.maker_fees_e6, WideUsdcE6::new(-15797));
+    assert_eq!(user.first_trade_ts, 1762800000000);
+    assert_eq!(user.trailing_volume(now_ms, 30), Ok(UsdcE6(105315000)));
+
+    let maker_user = ctx.user_settlement(&maker).await;
+    assert_eq!(maker_user.trailing_volume(now_ms, 30), Ok(UsdcE6(105315000)));
+}
+
+#[tokio::test]
+async fn test_fees_follow_volume_tiers() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    // 30天成交量达到100 USDC后taker 0.040% / maker 0.010%
+    let tiers = vec![
+        default_fee_tiers()[0],
+        FeeTier {
+            min_volume_e6: UsdcE6(100_000_000),
+            taker_rate_e5: 40,
+            maker_rate_e5: 10,
+        },
+    ];
+    let admin = ctx.payer.pubkey();
+    let ix = instruction::set_fee_schedule(&ctx.program_id, &admin, tiers, 1).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    // 手续费超出误差
+    let relayer = ctx.relayer.insecure_clone();
+    let mut trade = create_test_trade(taker, maker, 1);
+    trade.taker_fee_e6 = UsdcE6(47389);
+    let result = ctx.record_settlement(&relayer, &batch_id(1), vec![trade]).await;
+    assert_settlement_error(result, SettlementError::FeeMismatch);
+
+    // 第一笔按基础档位；之后双方30天成交量为105.315 USDC，进入第二档
+    let mut second = create_test_trade(taker, maker, 2);
+    let result = ctx
+        .record_settlement(&relayer, &batch_id(1), vec![create_test_trade(taker, maker, 1), second.clone()])
+        .await;
+    assert_settlement_error(result, SettlementError::FeeMismatch);
+
+    second.fee_rate_taker_e5 = 40;
+    second.fee_rate_maker_e5 = 10;
+    second.taker_fee_e6 = UsdcE6(42126);
+    second.maker_fee_e6 = UsdcE6(10532);
+    ctx.record_settlement(&relayer, &batch_id(1), vec![create_test_trade(taker, maker, 1), second])
+        .await
+        .unwrap();
+
+    let taker_stats = ctx.user_settlement(&taker).await;
+    assert_eq!(taker_stats.taker_fees_e6, WideUsdcE6::new(47391 + 42126));
+
+    // 档位必须从0开始且严格递增
+    let invalid = vec![FeeTier {
+        min_volume_e6: UsdcE6(1),
+        taker_rate_e5: 45,
+        maker_rate_e5: 15,
+    }];
+    let ix = instruction::set_fee_schedule(&ctx.program_id, &admin, invalid, 1).unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[]).await, SettlementError::InvalidFeeSchedule);
+
+    let ix = instruction::set_fee_schedule(&ctx.program_id, &relayer.pubkey(), default_fee_tiers(), 1)
+        .unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&relayer]).await, SettlementError::UnauthorizedAdmin);
+}
+
+#[tokio::test]
+async fn test_settlement_without_fee_schedule() {
+    let mut ctx = TestContext::start(Vec::new()).await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    let ix = instruction::initialize_config(&ctx.program_id, &ctx.payer.pubkey(), vec![ctx.relayer.pubkey()])
+        .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    ctx.register_market("BTC-PERP", MarketParams::STANDARD, default_limits()).await.unwrap();
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    // 未设置FeeSchedule时按trade自带的费率结算
+    let relayer = ctx.relayer.insecure_clone();
+    let mut trade = create_test_trade(taker, maker, 1);
+    trade.fee_rate_taker_e5 = 40;
+    trade.fee_rate_maker_e5 = 10;
+    trade.taker_fee_e6 = UsdcE6(42126);
+    trade.maker_fee_e6 = UsdcE6(10532);
+
+    // FeeSchedule位置必须是FeeSchedule PDA
+    let mut ix = instruction::record_settlement(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        BatchId::Uuid(batch_id(1)),
+        vec![trade.clone()],
+        MARKET_IDS,
+    )
+    .unwrap();
+    ix.accounts[5].pubkey = Pubkey::new_unique();
+    assert_settlement_error(ctx.process(&[ix], &[&relayer]).await, SettlementError::InvalidSettlementAccount);
+
+    ctx.record_settlement(&relayer, &batch_id(1), vec![trade.clone()]).await.unwrap();
+    assert_eq!(ctx.user_settlement(&taker).await.taker_fees_e6, WideUsdcE6::new(42126));
+
+    // 设置之后按档位校验
+    let ix = instruction::set_fee_schedule(&ctx.program_id, &ctx.payer.pubkey(), default_fee_tiers(), 1)
+        .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    trade.engine_seq = 2;
+    let result = ctx.record_settlement(&relayer, &batch_id(2), vec![trade]).await;
+    assert_settlement_error(result, SettlementError::FeeMismatch);
+}
+
+#[tokio::test]
+async fn test_maker_rebates() {
+    let mut ctx = TestContext::new().await;
+    let taker = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+
+    ctx.initialize_user(&taker).await;
+    ctx.initialize_user(&maker).await;
+
+    // maker返佣0.005%
+    let tiers = vec![FeeTier {
+        min_volume_e6: UsdcE6::ZERO,
+        taker_rate_e5: 45,
+        maker_rate_e5: -5,
+    }];
+    let ix = instruction::set_fee_schedule(&ctx.program_id, &ctx.payer.pubkey(), tiers, 1).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+
+    let relayer = ctx.relayer.insecure_clone();
+    let mut trade = create_test_trade(taker, maker, 1);
+    trade.fee_rate_maker_e5 = -5;
+
+    // 返佣费率对应的手续费必须为负
+    trade.maker_fee_e6 = UsdcE6(5266);
+    let result = ctx.record_settlement(&relayer, BATCH_ID, vec![trade.clone()]).await;
+    assert_settlement_error(result, SettlementError::InvalidTrade);
+
+    trade.maker_fee_e6 = UsdcE6(-5266);
+    ctx.record_settlement(&relayer, BATCH_ID, vec![trade]).await.unwrap();
+
+    let maker_stats = ctx.user_settlement(&maker).await;
+    assert_eq!(maker_stats.maker_fees_e6, WideUsdcE6::new(-5266));
+    assert_eq!(maker_stats.rebates_earned_e6, WideUsdcE6::new(5266));
+    assert_eq!(maker_stats.fees_paid_e6, WideUsdcE6::ZERO);
+
+    let taker_stats = ctx.user_settlement(&taker).await;
+    assert_eq!(taker_stats.fees_paid_e6, WideUsdcE6::new(47391));
+    assert_eq!(taker_stats.rebates_earned_e6, WideUsdcE6::ZERO);
+
+    assert_eq!(ctx.settlement_batch(BATCH_ID).await.total_fees_e6, UsdcE6(47391 - 5266));
+    assert_eq!(ctx.global_stats().await.total_maker_fees_e6, -5266);
+}
+
+#[tokio::test]
+async fn test_referral_fee_sharing() {
+    let mut ctx = TestContext::new().await;
+    let referred = Keypair::new();
+    let other = Pubkey::new_unique();
+    let referrer = Pubkey::new_unique();
+
+    ctx.fund(&referred.pubkey()).await;
+    ctx.initialize_user(&referred.pubkey()).await;
+    ctx.initialize_user(&other).await;
+
+    // 分成20%（仅admin，最多100%）
+    let admin = ctx.payer.pubkey();
+    let ix = instruction::set_referral_share(&ctx.program_id, &admin, 10_001).unwrap();
+    assert!(ctx.process(&[ix], &[]).await.is_err());
+    let relayer = ctx.relayer.insecure_clone();
+    let ix = instruction::set_referral_share(&ctx.program_id, &relayer.pubkey(), 2_000).unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&relayer]).await, SettlementError::UnauthorizedAdmin);
+    let ix = instruction::set_referral_share(&ctx.program_id, &admin, 2_000).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    assert_eq!(ctx.config().await.referral_share_bp, 2_000);
+
+    // 不能推荐自己，推荐人只能设置一次
+    let ix = instruction::set_referrer(&ctx.program_id, &referred.pubkey(), &referred.pubkey()).unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&referred]).await, SettlementError::InvalidReferrer);
+    let ix = instruction::set_referrer(&ctx.program_id, &referred.pubkey(), &referrer).unwrap();
+    ctx.process(&[ix], &[&referred]).await.unwrap();
+    let ix = instruction::set_referrer(&ctx.program_id, &referred.pubkey(), &other).unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&referred]).await, SettlementError::InvalidReferrer);
+
+    assert_eq!(ctx.user_settlement(&referred.pubkey()).await.referrer(), Some(referrer));
+    assert_eq!(ctx.referrer_stats(&referrer).await.referred_users, 1);
+
+    // 被推荐用户作为taker的两笔计入，作为maker的一笔不计入
+    let trades = vec![
+        create_test_trade(referred.pubkey(), other, 1),
+        create_test_trade(other, referred.pubkey(), 2),
+        create_test_trade(referred.pubkey(), other, 3),
+    ];
+
+    // 缺少ReferrerStats account
+    let result = ctx.record_settlement(&relayer, BATCH_ID, trades.clone()).await;
+    assert!(result.is_err());
+
+    let ix = instruction::record_settlement_with_referrers(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        BatchId::Uuid(BATCH_ID.to_string()),
+        trades,
+        MARKET_IDS,
+        &[(referred.pubkey(), referrer)],
+    )
+    .unwrap();
+    ctx.process(&[ix], &[&relayer]).await.unwrap();
+
+    // 47391 * 20% = 9478.2，逐笔向零截断
+    let stats = ctx.referrer_stats(&referrer).await;
+    assert_eq!(stats.referred_trades, 2);
+    assert_eq!(stats.referred_volume_e6, UsdcE6(2 * 105315000));
+    assert_eq!(stats.referred_fees_e6, UsdcE6(2 * 47391));
+    assert_eq!(stats.rewards_e6, UsdcE6(2 * 9478));
+    assert_eq!(stats.last_reward_ts, 1762897603000);
+}
+
+#[tokio::test]
+async fn test_positions_and_realized_pnl() {
+    let mut ctx = TestContext::new().await;
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    ctx.initialize_user(&alice).await;
+    ctx.initialize_user(&bob).await;
+
+    // alice作为taker卖出0.002 BTC @ 105315，bob作为maker买入
+    let mut open = create_test_trade(alice, bob, 1);
+    open.qty_e6 = QtyE6(2000);
+    open.notional_e6 = UsdcE6(210630000);
+    open.taker_fee_e6 = UsdcE6(94784);
+    open.maker_fee_e6 = UsdcE6(31595);
+
+    // bob作为taker卖出0.003 BTC @ 106000：平多0.002，再开空0.001
+    let mut flip = create_test_trade(bob, alice, 2);
+    flip.price_e6 = PriceE6(106000000000);
+    flip.qty_e6 = QtyE6(3000);
+    flip.notional_e6 = UsdcE6(318000000);
+    flip.taker_fee_e6 = UsdcE6(143100);
+    flip.maker_fee_e6 = UsdcE6(47700);
+
+    let relayer = ctx.relayer.insecure_clone();
+    ctx.record_settlement(&relayer, BATCH_ID, vec![open, flip]).await.unwrap();
+
+    // (106000 - 105315) * 0.002 = 1.37 USDC
+    let bob_btc = ctx.user_market_stats(&bob, 0).await.unwrap();
+    assert_eq!(bob_btc.position_qty_e6, QtyE6(-1000));
+    assert_eq!(bob_btc.avg_entry_price_e6, PriceE6(106000000000));
+    assert_eq!(bob_btc.realized_pnl_e6, UsdcE6(1370000));
+    assert_eq!(ctx.user_settlement(&bob).await.realized_pnl_e6, UsdcE6(1370000));
+
+    let alice_btc = ctx.user_market_stats(&alice, 0).await.unwrap();
+    assert_eq!(alice_btc.position_qty_e6, QtyE6(1000));
+    assert_eq!(alice_btc.avg_entry_price_e6, PriceE6(106000000000));
+    assert_eq!(alice_btc.realized_pnl_e6, UsdcE6(-1370000));
+    assert_eq!(ctx.user_settlement(&alice).await.realized_pnl_e6, UsdcE6(-1370000));
+}
+
+#[tokio::test]
+async fn test_record_funding() {
+    let mut ctx = TestContext::new().await;
+    let long = Pubkey::new_unique();
+    let short = Pubkey::new_unique();
+    let other = Pubkey::new_unique();
+
+    ctx.initialize_user(&long).await;
+    ctx.initialize_user(&short).await;
+    ctx.initialize_user(&other).await;
+
+    let relayer = ctx.relayer.insecure_clone();
+    let payments = |amounts: &[(Pubkey, i64)]| {
+        amounts
+            .iter()
+            .map(|&(wallet, amount)| FundingPayment { wallet, amount_e6: UsdcE6(amount) })
+            .collect::<Vec<_>>()
+    };
+
+    // 净额为1，默认误差为0
+    let unbalanced = payments(&[(long, 10_527), (short, -7_000), (other, -3_526)]);
+    let ix = instruction::record_funding(&ctx.program_id, &relayer.pubkey(), 1, "BTC-PERP", unbalanced.clone())
+        .unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&relayer]).await, SettlementError::FundingImbalance);
+
+    let duplicate = payments(&[(long, 7_000), (long, -7_000)]);
+    let ix = instruction::record_funding(&ctx.program_id, &relayer.pubkey(), 1, "BTC-PERP", duplicate).unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&relayer]).await, SettlementError::InvalidFunding);
+
+    let admin = ctx.payer.pubkey();
+    // 误差为u64，不受u16上限限制
+    let ix = instruction::set_funding_tolerance(&ctx.program_id, &admin, 100_000).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    assert_eq!(ctx.config().await.funding_tolerance_e6, 100_000);
+
+    let ix = instruction::record_funding(&ctx.program_id, &relayer.pubkey(), 1, "BTC-PERP", unbalanced.clone())
+        .unwrap();
+    ctx.process(&[ix], &[&relayer]).await.unwrap();
+
+    // 同一周期只能记录一次；其他市场的同一周期独立
+    let ix = instruction::record_funding(&ctx.program_id, &relayer.pubkey(), 1, "BTC-PERP", unbalanced)
+        .unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&relayer]).await, SettlementError::AccountAlreadyExists);
+
+    // 提前向ETH-PERP周期1的PDA转账不影响记录
+    let (eth_pda, _) = instruction::find_funding_epoch_address("ETH-PERP", 1, &ctx.program_id);
+    ctx.fund(&eth_pda).await;
+
+    let ix = instruction::record_funding(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        1,
+        "ETH-PERP",
+        payments(&[(long, -2_000), (short, 2_000)]),
+    )
+    .unwrap();
+    ctx.process(&[ix], &[&relayer]).await.unwrap();
+
+    let long_user = ctx.user_settlement(&long).await;
+    assert_eq!(long_user.funding_paid_e6, UsdcE6(10_527));
+    assert_eq!(long_user.funding_received_e6, UsdcE6(2_000));
+    let short_user = ctx.user_settlement(&short).await;
+    assert_eq!(short_user.funding_paid_e6, UsdcE6(2_000));
+    assert_eq!(short_user.funding_received_e6, UsdcE6(7_000));
+
+    let (pda, _) = instruction::find_funding_epoch_address("BTC-PERP", 1, &ctx.program_id);
+    let account = ctx.banks_client.get_account(pda).await.unwrap().unwrap();
+    let epoch = FundingEpoch::try_from_slice(&account.data).unwrap();
+    assert_eq!(epoch.market_id, 0);
+    assert_eq!(epoch.payment_count, 3);
+    assert_eq!(epoch.total_paid_e6, UsdcE6(10_527));
+    assert_eq!(epoch.total_received_e6, UsdcE6(10_526));
+    assert_eq!(epoch.imbalance_e6, UsdcE6(1));
+    assert_eq!(epoch.relayer, relayer.pubkey());
+
+    // 暂停或下架的市场不能记录资金费
+    let ix = instruction::pause(&ctx.program_id, &admin, Some(2)).unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    let ix = instruction::record_funding(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        1,
+        "SOL-PERP",
+        payments(&[(long, 500), (short, -500)]),
+    )
+    .unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&relayer]).await, SettlementError::MarketPaused);
+
+    let ix = instruction::set_market_status(&ctx.program_id, &admin, "ETH-PERP", MarketStatus::Delisted)
+        .unwrap();
+    ctx.process(&[ix], &[]).await.unwrap();
+    let ix = instruction::record_funding(
+        &ctx.program_id,
+        &relayer.pubkey(),
+        2,
+        "ETH-PERP",
+        payments(&[(long, 500), (short, -500)]),
+    )
+    .unwrap();
+    assert_settlement_error(ctx.process(&[ix], &[&relayer]).await, SettlementError::MarketDelisted);
+}
+
+/// 1.0.0格式的RecordSettlement指令：UUID字符串batch id + trades，accounts为每个trade的
+/// taker/maker UserSettlement，最后是relayer签名。用于与当前版本比较交易大小
+fn record_settlement_v1_0_0(program_id: &Pubkey, relayer: &Pubkey, trades: &[CompleteTrade]) -> Instruction {
+    let mut data = vec![1u8];
+    BATCH_ID.to_string().serialize(&mut data).unwrap();
+    trades.serialize(&mut data).unwrap();
+    let mut accounts = trades
+        .iter()
+        .flat_map(|trade| [trade.taker_wallet, trade.maker_wallet])
+        .map(|wallet| AccountMeta::new(instruction::find_user_settlement_address(&wallet, program_id).0, false))
+        .collect::<Vec<_>>();
+    accounts.push(AccountMeta::new_readonly(*relayer, true));
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// 单笔交易最多容纳的trade数，size(n)为n个trade时的交易大小
+fn max_trades_per_transaction(size: impl Fn(u64) -> usize) -> u64 {
+    (1..=16).take_while(|&count| size(count) <= PACKET_DATA_SIZE).count() as u64
+}
+
+#[test]
+fn test_record_settlement_transaction_size() {
+    let program_id = settlement_program::id();
+    let relayer = Pubkey::new_unique();
+    let (taker, maker) = (Pubkey::new_unique(), Pubkey::new_unique());
+    assert_eq!(create_production_trade(taker, maker, 1).try_to_vec().unwrap().len(), 391);
+
+    let trades = |count: u64, distinct_users: bool, production: bool| -> Vec<CompleteTrade> {
+        (1..=count)
+            .map(|seq| {
+                let (taker, maker) = match distinct_users {
+                    true => (Pubkey::new_unique(), Pubkey::new_unique()),
+                    false => (taker, maker),
+                };
+                match production {
+                    true => create_production_trade(taker, maker, seq),
+                    false => create_test_trade(taker, maker, seq),
+                }
+            })
+            .collect()
+    };
+    let build = |trades: Vec<CompleteTrade>| {
+        instruction::record_settlement(&program_id, &relayer, BatchId::Binary([7; 16]), trades, MARKET_IDS).unwrap()
+    };
+    let legacy_size = |ix: Instruction| {
+        bincode::serialized_size(&Transaction::new_with_payer(&[ix], Some(&relayer))).unwrap() as usize
+    };
+    // relayer长期使用的lookup table包含除SettlementBatch外的所有非签名account
+    let lookup_table_size = |ix: Instruction| {
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: instruction::record_settlement_lookup_table_addresses(&ix),
+        };
+        let message = instruction::record_settlement_v0_message(&relayer, &[ix], &[table], Hash::default()).unwrap();
+        let tx = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::V0(message),
+        };
+        bincode::serialized_size(&tx).unwrap() as usize
+    };
+
+    // 见docs/COMPUTE_UNITS.md：(已发布版本, legacy交易, v0 + ALT)最多容纳的trade数。
+    // 391字节的trade只有2个能放进一笔交易（3 * 391 + 签名 > 1232），任何account布局都无法超过1.0.0；
+    // 字段较短的trade在2T个用户时，v0 + ALT比1.0.0多容纳1个
+    for (production, distinct_users, expected) in [
+        (true, false, (2, 1, 2)),
+        (true, true, (2, 1, 2)),
+        (false, false, (4, 2, 4)),
+        (false, true, (3, 2, 4)),
+    ] {
+        let released = max_trades_per_transaction(|count| {
+            legacy_size(record_settlement_v1_0_0(&program_id, &relayer, &trades(count, distinct_users, production)))
+        });
+        let legacy = max_trades_per_transaction(|count| legacy_size(build(trades(count, distinct_users, production))));
+        let lookup_table =
+            max_trades_per_transaction(|count| lookup_table_size(build(trades(count, distinct_users, production))));
+        assert_eq!((released, legacy, lookup_table), expected, "production {} distinct {}", production, distinct_users);
+        assert!(lookup_table >= released);
+    }
+}
+
+/// 测量RecordSettlement的CU，结果记录在docs/COMPUTE_UNITS.md
+///
+/// processor!注册的native processor不计CU，需要先`cargo build-sbf`，再设置SBF_OUT_DIR运行：
+/// `SBF_OUT_DIR=$(pwd)/target/deploy cargo test --test integration_test measure_record_settlement_compute_units -- --ignored --nocapture`
+#[tokio::test]
+#[ignore]
+async fn measure_record_settlement_compute_units() {
+    if std::env::var("SBF_OUT_DIR").is_err() && std::env::var("BPF_OUT_DIR").is_err() {
+        eprintln!("SBF_OUT_DIR is not set: native processors do not consume compute units");
+        return;
+    }
+
+    let mut ctx = TestContext::new().await;
+    let relayer = ctx.relayer.insecure_clone();
+    let wallets: Vec<Pubkey> = (0..8).map(|_| Pubkey::new_unique()).collect();
+    for wallet in &wallets {
+        ctx.initialize_user(wallet).await;
+    }
+
+    // 先创建所有UserMarketStats，之后测量的是已有account的情况
+    let warmup = wallets
+        .chunks(2)
+        .zip(1..)
+        .map(|(pair, seq)| create_production_trade(pair[0], pair[1], seq))
+        .collect::<Vec<_>>();
+    let next_seq = warmup.len() as u64 + 1;
+    ctx.record_settlement(&relayer, &batch_id(0), warmup).await.unwrap();
+
+    for count in 1..=4usize {
+        for distinct_users in [false, true] {
+            let trades = (0..count)
+                .map(|i| {
+                    let pair = if distinct_users { &wallets[2 * i..2 * i + 2] } else { &wallets[..2] };
+                    create_production_trade(pair[0], pair[1], next_seq + i as u64)
+                })
+                .collect();
+            let batch = BatchId::Uuid(batch_id(count as u64 * 2 + distinct_users as u64));
+            let ix = instruction::record_settlement(&ctx.program_id, &relayer.pubkey(), batch, trades, MARKET_IDS)
+                .unwrap();
+            let tx = Transaction::new_signed_with_payer(
+                &[ix],
+                Some(&ctx.payer.pubkey()),
+                &[&ctx.payer, &relayer],
+                ctx.last_blockhash,
+            );
+            let simulation = ctx.banks_client.simulate_transaction(tx).await.unwrap();
+            assert!(simulation.result.unwrap().is_ok());
+            println!(
+                "trades={} users={} units_consumed={}",
+                count,
+                if distinct_users { 2 * count } else { 2 },
+                simulation.simulation_details.unwrap().units_consumed
+            );
+        }
+    }
+}
@@ -114,8 +114,8 @@ fn main() {
         println!("【手续费】");
         println!(" 17. Taker Fee: {} USDC (e6: {})", trade.taker_fee_e6 as f64 / 1_000_000.0, trade.taker_fee_e6);
         println!(" 18. Maker Fee: {} USDC (e6: {})", trade.maker_fee_e6 as f64 / 1_000_000.0, trade.maker_fee_e6);
-        println!(" 19a. Taker Fee Rate: {}bp ({}%)", trade.fee_rate_taker_bp, trade.fee_rate_taker_bp as f64 / 10000.0);
-        println!(" 19b. Maker Fee Rate: {}bp ({}%)", trade.fee_rate_maker_bp, trade.fee_rate_maker_bp as f64 / 10000.0);
+        println!(" 19a. Taker Fee Rate: {} (1/100000, {}%)", trade.fee_rate_taker_e5, trade.fee_rate_taker_e5 as f64 / 1000.0);
+        println!(" 19b. Maker Fee Rate: {} (1/100000, {}%)", trade.fee_rate_maker_e5, trade.fee_rate_maker_e5 as f64 / 1000.0);
         println!();
     }
     